@@ -0,0 +1,171 @@
+use std::fs::File;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::external_models::ExternalTransaction;
+use crate::utils::{get_value_or_empty, to_string};
+
+lazy_static! {
+    static ref AMOUNT_RULE: Regex = Regex::new(r"^amount\s*(<=|>=|<|>)\s*(-?[0-9.]+)$").unwrap();
+}
+
+// A condition for adding a missing transaction without asking, like
+// 'amount<5000', which compares the absolute value of the amount, or 'description~^CARD'
+#[derive(Debug)]
+pub enum AcceptRule {
+    Amount(String, Decimal),
+    Description(Regex),
+}
+
+impl AcceptRule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let rule = rule.trim();
+        if let Some(pattern) = rule.strip_prefix("description~") {
+            return Ok(AcceptRule::Description(Regex::new(pattern)?));
+        }
+        let caps = AMOUNT_RULE.captures(rule).with_context(|| {
+            format!(
+                "Invalid rule:'{}', expected like amount<100 or description~regex",
+                rule
+            )
+        })?;
+        Ok(AcceptRule::Amount(caps[1].to_owned(), caps[2].parse()?))
+    }
+
+    fn matches(&self, transaction: &ExternalTransaction) -> bool {
+        match self {
+            AcceptRule::Amount(operator, limit) => {
+                let Some(amount) = transaction.get_amount().map(|amount| amount.abs()) else {
+                    return false;
+                };
+                match operator.as_str() {
+                    "<" => amount < *limit,
+                    "<=" => amount <= *limit,
+                    ">" => amount > *limit,
+                    _ => amount >= *limit,
+                }
+            }
+            AcceptRule::Description(pattern) => transaction
+                .get_description_or_category()
+                .is_some_and(|description| pattern.is_match(&description)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    Ask,
+    Pending,
+}
+
+// How the missing transactions are added: the ones passing every rule are added,
+// the rest are asked interactively, or left pending, if there is no input
+#[derive(Debug, Default)]
+pub struct Acceptance {
+    pub rules: Vec<AcceptRule>,
+    pub accept_all: bool,
+    pub no_input: bool,
+    // a confident suggestion is used without asking too, not only a rule or the given account
+    pub accept_suggested: bool,
+}
+
+impl Acceptance {
+    pub fn decide(&self, transaction: &ExternalTransaction) -> Decision {
+        let accepted = if self.rules.is_empty() {
+            self.accept_all
+        } else {
+            self.rules.iter().all(|rule| rule.matches(transaction))
+        };
+        match (accepted, self.no_input) {
+            (true, _) => Decision::Accept,
+            (false, true) => Decision::Pending,
+            (false, false) => Decision::Ask,
+        }
+    }
+}
+
+// Saves the transactions, which were not added, so they can be reviewed later
+pub fn write_pending_report(file: &str, transactions: &[ExternalTransaction]) -> Result<()> {
+    let output =
+        File::create(file).with_context(|| format!("Unable to create the report {}", file))?;
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record([
+        "date",
+        "booking_date",
+        "amount",
+        "fee",
+        "description",
+        "category",
+        "other_account",
+        "other_account_name",
+    ])?;
+    for transaction in transactions {
+        writer.write_record([
+            to_string(transaction.date),
+            to_string(transaction.booking_date),
+            transaction
+                .amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default(),
+            transaction
+                .transaction_fee
+                .map(|fee| fee.to_string())
+                .unwrap_or_default(),
+            get_value_or_empty(&transaction.description).to_owned(),
+            get_value_or_empty(&transaction.category).to_owned(),
+            get_value_or_empty(&transaction.other_account).to_owned(),
+            get_value_or_empty(&transaction.other_account_name).to_owned(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(amount: i64, description: &str) -> ExternalTransaction {
+        ExternalTransaction {
+            date: None,
+            booking_date: None,
+            amount: Some(Decimal::from(amount)),
+            category: None,
+            description: Some(description.to_owned()),
+            other_account: None,
+            other_account_name: None,
+            textual_date: None,
+            transaction_fee: None,
+            reference: None,
+        }
+    }
+
+    #[test]
+    fn test_rules_are_all_required() {
+        let acceptance = Acceptance {
+            rules: vec![
+                AcceptRule::parse("amount < 5000").unwrap(),
+                AcceptRule::parse("description~(?i)^card").unwrap(),
+            ],
+            accept_all: false,
+            no_input: true,
+            accept_suggested: false,
+        };
+        assert_eq!(
+            acceptance.decide(&transaction(-4999, "CARD Tesco")),
+            Decision::Accept
+        );
+        assert_eq!(
+            acceptance.decide(&transaction(-5000, "CARD Tesco")),
+            Decision::Pending
+        );
+        assert_eq!(
+            acceptance.decide(&transaction(-10, "Transfer")),
+            Decision::Pending
+        );
+        assert!(AcceptRule::parse("amount=5").is_err());
+    }
+}
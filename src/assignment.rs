@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+// A possible pair of a row and a column, with its cost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub row: usize,
+    pub column: usize,
+    pub cost: u32,
+}
+
+fn find(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+// The edges of the independent groups of rows and columns, so each group can be solved alone
+fn components(edges: &[Edge], rows: usize, columns: usize) -> Vec<Vec<Edge>> {
+    // the rows are the first nodes, followed by the columns
+    let mut parents: Vec<usize> = (0..rows + columns).collect();
+    for edge in edges {
+        let row = find(&mut parents, edge.row);
+        let column = find(&mut parents, rows + edge.column);
+        parents[row] = column;
+    }
+    let mut result: BTreeMap<usize, Vec<Edge>> = BTreeMap::new();
+    for edge in edges {
+        let root = find(&mut parents, edge.row);
+        result.entry(root).or_default().push(*edge);
+    }
+    result.into_values().collect()
+}
+
+// Hungarian method on a dense matrix with at most as many rows as columns,
+// returns the column of each row
+fn hungarian(costs: &[Vec<i64>]) -> Vec<usize> {
+    let rows = costs.len();
+    let columns = costs.first().map(Vec::len).unwrap_or_default();
+    // 1-based potentials and assignment, the 0th column is a sentinel
+    let mut row_potential = vec![0i64; rows + 1];
+    let mut column_potential = vec![0i64; columns + 1];
+    let mut assigned_row = vec![0usize; columns + 1];
+    let mut way = vec![0usize; columns + 1];
+    for row in 1..=rows {
+        assigned_row[0] = row;
+        let mut current = 0;
+        let mut min_values = vec![i64::MAX; columns + 1];
+        let mut used = vec![false; columns + 1];
+        loop {
+            used[current] = true;
+            let current_row = assigned_row[current];
+            let mut delta = i64::MAX;
+            let mut next = 0;
+            for column in 1..=columns {
+                if used[column] {
+                    continue;
+                }
+                let reduced = costs[current_row - 1][column - 1]
+                    - row_potential[current_row]
+                    - column_potential[column];
+                if reduced < min_values[column] {
+                    min_values[column] = reduced;
+                    way[column] = current;
+                }
+                if min_values[column] < delta {
+                    delta = min_values[column];
+                    next = column;
+                }
+            }
+            for column in 0..=columns {
+                if used[column] {
+                    row_potential[assigned_row[column]] += delta;
+                    column_potential[column] -= delta;
+                } else {
+                    min_values[column] -= delta;
+                }
+            }
+            current = next;
+            if assigned_row[current] == 0 {
+                break;
+            }
+        }
+        loop {
+            let previous = way[current];
+            assigned_row[current] = assigned_row[previous];
+            current = previous;
+            if current == 0 {
+                break;
+            }
+        }
+    }
+    let mut result = vec![0; rows];
+    for column in 1..=columns {
+        if assigned_row[column] != 0 {
+            result[assigned_row[column] - 1] = column - 1;
+        }
+    }
+    result
+}
+
+fn solve_component(edges: &[Edge]) -> Vec<Edge> {
+    let mut row_ids: Vec<usize> = edges.iter().map(|edge| edge.row).collect();
+    let mut column_ids: Vec<usize> = edges.iter().map(|edge| edge.column).collect();
+    row_ids.sort_unstable();
+    row_ids.dedup();
+    column_ids.sort_unstable();
+    column_ids.dedup();
+    // the matrix needs at most as many rows as columns
+    let transposed = row_ids.len() > column_ids.len();
+    let (outer, inner) = if transposed {
+        (&column_ids, &row_ids)
+    } else {
+        (&row_ids, &column_ids)
+    };
+    // a missing edge costs more than any complete assignment, so the most pairs are made first
+    let max_cost = edges
+        .iter()
+        .map(|edge| i64::from(edge.cost))
+        .max()
+        .unwrap_or_default();
+    let missing = (max_cost + 1) * outer.len() as i64 + 1;
+    let mut costs = vec![vec![missing; inner.len()]; outer.len()];
+    let mut lookup = BTreeMap::new();
+    for edge in edges {
+        let (outer_id, inner_id) = if transposed {
+            (edge.column, edge.row)
+        } else {
+            (edge.row, edge.column)
+        };
+        let i = outer.binary_search(&outer_id).expect("Known id");
+        let j = inner.binary_search(&inner_id).expect("Known id");
+        costs[i][j] = costs[i][j].min(i64::from(edge.cost));
+        lookup.insert((i, j), edge);
+    }
+    hungarian(&costs)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, j)| lookup.get(&(i, j)).map(|edge| **edge))
+        .collect()
+}
+
+// Picks the edges, so every row and column is used at most once, the most pairs are made,
+// and among those the total cost is the lowest
+pub fn min_cost_assignment(edges: &[Edge], rows: usize, columns: usize) -> Vec<Edge> {
+    let mut result: Vec<Edge> = components(edges, rows, columns)
+        .iter()
+        .flat_map(|component| solve_component(component))
+        .collect();
+    result.sort_by_key(|edge| edge.row);
+    result
+}
+
+// Collects the subsets, whose sum is accepted, until there are limit of them
+struct SubsetSearch<'a, F> {
+    amounts: &'a [Decimal],
+    accepts: F,
+    limit: usize,
+    chosen: Vec<usize>,
+    found: Vec<Vec<usize>>,
+}
+
+impl<F: Fn(Decimal) -> bool> SubsetSearch<'_, F> {
+    fn search(&mut self, size: usize, start: usize, sum: Decimal) {
+        if self.chosen.len() == size {
+            if (self.accepts)(sum) {
+                self.found.push(self.chosen.clone());
+            }
+            return;
+        }
+        for idx in start..self.amounts.len() {
+            if self.found.len() == self.limit {
+                return;
+            }
+            self.chosen.push(idx);
+            self.search(size, idx + 1, sum + self.amounts[idx]);
+            self.chosen.pop();
+        }
+    }
+}
+
+pub struct Subset {
+    pub indexes: Vec<usize>,
+    // an other subset is accepted too
+    pub ambiguous: bool,
+}
+
+// The indexes of at least two, at most max_size amounts, whose sum is accepted,
+// the smaller subsets, and the ones with the earlier amounts are preferred
+pub fn subset_sum(
+    amounts: &[Decimal],
+    max_size: usize,
+    accepts: impl Fn(Decimal) -> bool,
+) -> Option<Subset> {
+    let mut search = SubsetSearch {
+        amounts,
+        accepts,
+        limit: 2,
+        chosen: Vec::with_capacity(max_size),
+        found: Vec::new(),
+    };
+    for size in 2..=max_size.min(amounts.len()) {
+        search.search(size, 0, Decimal::ZERO);
+        if search.found.len() == search.limit {
+            break;
+        }
+    }
+    let mut found = search.found.into_iter();
+    let indexes = found.next()?;
+    Some(Subset {
+        indexes,
+        ambiguous: found.next().is_some(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(row: usize, column: usize, cost: u32) -> Edge {
+        Edge { row, column, cost }
+    }
+
+    #[test]
+    fn test_assignment_is_globally_optimal() {
+        // first-fit would pair row 0 with column 0, and leave row 1 alone
+        let edges = [edge(0, 0, 1), edge(0, 1, 2), edge(1, 0, 1)];
+        assert_eq!(
+            min_cost_assignment(&edges, 2, 2),
+            vec![edge(0, 1, 2), edge(1, 0, 1)]
+        );
+        // the lower total cost wins, independent groups are solved separately
+        let edges = [
+            edge(0, 0, 0),
+            edge(0, 1, 3),
+            edge(1, 0, 1),
+            edge(1, 1, 5),
+            edge(2, 2, 7),
+            edge(3, 2, 4),
+        ];
+        assert_eq!(
+            min_cost_assignment(&edges, 4, 3),
+            vec![edge(0, 1, 3), edge(1, 0, 1), edge(3, 2, 4)]
+        );
+    }
+
+    #[test]
+    fn test_subset_sum() {
+        let amounts: Vec<Decimal> = [-1000, -30, -500, -470]
+            .into_iter()
+            .map(Decimal::from)
+            .collect();
+        let equals = |target: i64| move |sum: Decimal| sum == Decimal::from(target);
+        let indexes = |subset: Option<Subset>| subset.map(|subset| subset.indexes);
+        assert_eq!(
+            indexes(subset_sum(&amounts, 3, equals(-1030))),
+            Some(vec![0, 1])
+        );
+        let subset = subset_sum(&amounts, 3, equals(-1000)).unwrap();
+        assert_eq!(subset.indexes, vec![1, 2, 3]);
+        assert!(!subset.ambiguous);
+        assert!(subset_sum(&amounts, 2, equals(-1000)).is_none());
+        assert!(subset_sum(&amounts, 4, equals(-5)).is_none());
+        // -1500 is -1000 + -500, or -1000 + -30 + -470
+        assert!(subset_sum(&amounts, 3, equals(-1500)).unwrap().ambiguous);
+    }
+}
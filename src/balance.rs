@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use console::{Term, style};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::external_models::{Balance, ExternalTransactionList, Matching};
+use crate::models::Account;
+use crate::query::transactions::TransactionQuery;
+
+// A balance of the statement, and the balance of the book at the same time
+#[derive(Debug, PartialEq)]
+pub struct BalanceDifference {
+    pub date: NaiveDate,
+    pub statement: Decimal,
+    pub book: Decimal,
+}
+
+impl BalanceDifference {
+    pub fn is_equal(&self) -> bool {
+        self.statement == self.book
+    }
+}
+
+// The first day, where the statement and the book moved differently
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub date: NaiveDate,
+    pub statement: Decimal,
+    pub book: Decimal,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BalanceCheck {
+    pub opening: Option<BalanceDifference>,
+    pub closing: Option<BalanceDifference>,
+    pub divergence: Option<Divergence>,
+}
+
+impl BalanceCheck {
+    pub fn differs(&self) -> bool {
+        self.opening
+            .iter()
+            .chain(self.closing.iter())
+            .any(|difference| !difference.is_equal())
+    }
+}
+
+fn daily_sums(amounts: impl Iterator<Item = (NaiveDate, Decimal)>) -> BTreeMap<NaiveDate, Decimal> {
+    let mut result = BTreeMap::new();
+    for (date, amount) in amounts {
+        *result.entry(date).or_insert(Decimal::ZERO) += amount;
+    }
+    result
+}
+
+// The daily sums of the account, by the posting dates of the transactions
+fn book_daily_sums(
+    connection: &mut SqliteConnection,
+    account: &Account,
+) -> BTreeMap<NaiveDate, Decimal> {
+    let query = TransactionQuery {
+        limit: i64::MAX,
+        txid_filter: None,
+        account_filter: Some(account.guid.clone()),
+        description_filter: None,
+        memo_filter: None,
+        before_filter: None,
+        after_filter: None,
+    };
+    daily_sums(query.execute(connection).iter().filter_map(|(split, tx)| {
+        tx.posting()
+            .map(|posted| (posted.date(), split.get_quantity_as_decimal()))
+    }))
+}
+
+// Compares the balances of the statement with the book, where the opening balance is before
+// the first day of the statement, and the closing balance is at the end of its day. The days
+// are walked to find where the two sides diverge first, by the dates used for matching, as
+// the transactions are posted on them, like a card payment on its spending date.
+pub fn check_balances(
+    book: &BTreeMap<NaiveDate, Decimal>,
+    list: &ExternalTransactionList,
+    matching: Matching,
+) -> BalanceCheck {
+    // the balance of the bank changes, when the row is booked
+    let statement = daily_sums(list.0.iter().filter_map(|transaction| {
+        Some((
+            transaction.booking_date.or(transaction.date)?,
+            transaction.amount?,
+        ))
+    }));
+    let matched = daily_sums(list.0.iter().filter_map(|transaction| {
+        Some((
+            transaction.get_matching_date(matching)?,
+            transaction.amount?,
+        ))
+    }));
+    let balances = &list.3;
+    let first_day = statement
+        .keys()
+        .next()
+        .copied()
+        .or(balances.opening.as_ref().and_then(|balance| balance.date));
+    let last_day = balances
+        .closing
+        .as_ref()
+        .and_then(|balance| balance.date)
+        .or(statement.keys().next_back().copied());
+    let compare = |balance: &Option<Balance>, date: Option<NaiveDate>, until_end_of_day: bool| {
+        let (balance, date) = (balance.as_ref()?, date?);
+        let book = if until_end_of_day {
+            book.range(..=date).map(|(_, amount)| *amount).sum()
+        } else {
+            book.range(..date).map(|(_, amount)| *amount).sum()
+        };
+        Some(BalanceDifference {
+            date,
+            statement: balance.amount,
+            book,
+        })
+    };
+    let mut check = BalanceCheck {
+        opening: compare(&balances.opening, first_day, false),
+        closing: compare(&balances.closing, last_day, true),
+        divergence: None,
+    };
+    check.divergence = match (first_day, last_day) {
+        (Some(first), Some(last)) if check.differs() && first <= last => {
+            // a row can be spent before the first booking day
+            let first = matched
+                .keys()
+                .next()
+                .map_or(first, |matched_first| first.min(*matched_first));
+            let days: BTreeSet<NaiveDate> = matched
+                .range(first..=last)
+                .chain(book.range(first..=last))
+                .map(|(date, _)| *date)
+                .collect();
+            days.into_iter().find_map(|date| {
+                let statement = matched.get(&date).copied().unwrap_or_default();
+                let book = book.get(&date).copied().unwrap_or_default();
+                (statement != book).then_some(Divergence {
+                    date,
+                    statement,
+                    book,
+                })
+            })
+        }
+        _ => None,
+    };
+    check
+}
+
+fn report_difference(name: &str, difference: &BalanceDifference, term: &Term) -> Result<()> {
+    let date = difference.date.format("%Y-%m-%d");
+    if difference.is_equal() {
+        term.write_line(&format!(
+            "The {} balance {} at {} equals the book",
+            name,
+            style(difference.statement).green(),
+            date
+        ))?;
+    } else {
+        term.write_line(&format!(
+            "The {} balance {} at {} differs from the book balance {} by {}",
+            name,
+            style(difference.statement).red(),
+            date,
+            style(difference.book).red(),
+            style(difference.statement - difference.book).red()
+        ))?;
+    }
+    Ok(())
+}
+
+// Reports, whether the balances of the statement agree with the account in the book
+pub fn verify_balances(
+    connection: &mut SqliteConnection,
+    account: &Account,
+    list: &ExternalTransactionList,
+    matching: Matching,
+    term: &Term,
+) -> Result<()> {
+    let check = check_balances(&book_daily_sums(connection, account), list, matching);
+    if let Some(opening) = &check.opening {
+        report_difference("opening", opening, term)?;
+    }
+    if let Some(closing) = &check.closing {
+        report_difference("closing", closing, term)?;
+    }
+    match &check.divergence {
+        Some(divergence) => term.write_line(&format!(
+            "The running balances diverge first on {}, the statement changes by {}, the book by {}",
+            style(divergence.date.format("%Y-%m-%d")).red(),
+            style(divergence.statement).cyan(),
+            style(divergence.book).cyan()
+        ))?,
+        None if check.differs() => term.write_line(
+            "Every day of the statement moves the balances equally, they differ from the start",
+        )?,
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external_models::{ExternalTransaction, StatementBalances};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    fn row(day: u32, amount: i64) -> ExternalTransaction {
+        ExternalTransaction {
+            date: Some(date(day)),
+            booking_date: None,
+            amount: Some(Decimal::from(amount)),
+            category: None,
+            description: None,
+            other_account: None,
+            other_account_name: None,
+            textual_date: None,
+            transaction_fee: None,
+            reference: None,
+        }
+    }
+
+    fn list(rows: Vec<ExternalTransaction>, opening: i64, closing: i64) -> ExternalTransactionList {
+        ExternalTransactionList(
+            rows,
+            Some(date(5)),
+            Some(date(20)),
+            StatementBalances {
+                opening: Some(Balance {
+                    date: None,
+                    amount: Decimal::from(opening),
+                }),
+                closing: Some(Balance {
+                    date: Some(date(31)),
+                    amount: Decimal::from(closing),
+                }),
+            },
+        )
+    }
+
+    #[test]
+    fn test_check_balances() {
+        let book = daily_sums(
+            [(1, 1000), (5, -100), (12, -50), (12, -20), (25, -300)]
+                .into_iter()
+                .map(|(day, amount)| (date(day), Decimal::from(amount))),
+        );
+        let rows = vec![row(5, -100), row(12, -70), row(25, -300)];
+        let check = check_balances(&book, &list(rows, 1000, 530), Matching::BySpending);
+        assert!(check.opening.unwrap().is_equal());
+        assert!(check.closing.unwrap().is_equal());
+        assert_eq!(check.divergence, None);
+
+        // the book misses a payment of the 20th
+        let rows = vec![row(5, -100), row(12, -70), row(20, -15), row(25, -300)];
+        let check = check_balances(&book, &list(rows, 1000, 515), Matching::BySpending);
+        assert_eq!(
+            check.closing,
+            Some(BalanceDifference {
+                date: date(31),
+                statement: Decimal::from(515),
+                book: Decimal::from(530),
+            })
+        );
+        assert_eq!(
+            check.divergence,
+            Some(Divergence {
+                date: date(20),
+                statement: Decimal::from(-15),
+                book: Decimal::ZERO,
+            })
+        );
+
+        // the payment of the 12th is booked by the bank on the 14th, the book has the 12th
+        let mut late = row(12, -70);
+        late.booking_date = Some(date(14));
+        let rows = vec![row(5, -100), late, row(20, -15), row(25, -300)];
+        let check = check_balances(&book, &list(rows, 1000, 515), Matching::BySpending);
+        assert_eq!(
+            check.divergence.map(|divergence| divergence.date),
+            Some(date(20))
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
+use rust_decimal::Decimal;
+
+use crate::external_models::DateField;
+use crate::reconcile::ReconcileState;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -10,11 +14,15 @@ pub(crate) struct Cli {
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Commands {
     ListAccounts(ListAccountsArgs),
     Transactions(TransactionsArgs),
     Correlate(CorrelateArgs),
     Commodities(CommoditiesArgs),
+    Export(ExportArgs),
+    Duplicates(DuplicatesArgs),
+    Undo(UndoArgs),
     Completions {
         #[arg(value_enum)]
         shell: Shell,
@@ -61,6 +69,14 @@ pub struct TransactionsArgs {
     #[arg(long = "move-split", short = 'm')]
     pub move_split: bool,
 
+    // Write into the book, even if it's locked by GnuCash
+    #[arg(long = "force")]
+    pub force: bool,
+
+    // Print what would be written into the book, without writing anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
     #[command(flatten)]
     pub account: DefaultAccountParams,
     #[command(flatten)]
@@ -77,7 +93,8 @@ pub struct CorrelateArgs {
     #[arg(long = "sheet-name", short = 's')]
     pub sheet_name: Option<String>,
 
-    // The format of the sheet
+    // The format of the input: a built-in sheet format, csv, ofx, camt, mt940, qif,
+    // or the name or path of a format file. Detected from the content, if not specified
     #[arg(long = "format", short = 'f')]
     pub format: Option<String>,
 
@@ -85,6 +102,44 @@ pub struct CorrelateArgs {
     #[arg(long = "by-booking-date", short = 'd')]
     pub by_booking_date: bool,
 
+    // The date of the statement rows used for matching, instead of the one of the format
+    #[arg(long = "match-date", conflicts_with = "by_booking_date")]
+    pub match_date: Option<DateField>,
+
+    // The maximum number of days between the booked and the statement date, 10 by default
+    #[arg(long = "max-days")]
+    pub max_days: Option<u32>,
+
+    // How many days the booked transaction can be earlier than the statement date
+    #[arg(long = "days-before")]
+    pub days_before: Option<u32>,
+
+    // How many days the booked transaction can be later than the statement date
+    #[arg(long = "days-after")]
+    pub days_after: Option<u32>,
+
+    // How much the booked amount can differ from the amount of the statement row
+    #[arg(long = "amount-tolerance")]
+    pub amount_tolerance: Option<Decimal>,
+
+    // How many percent the booked amount can differ from the amount of the statement row
+    #[arg(long = "amount-tolerance-percent")]
+    pub amount_tolerance_percent: Option<Decimal>,
+
+    // The currency of the statement, like EUR, the amounts are converted to the commodity
+    // of the account by the prices of the book, when it differs
+    #[arg(long = "statement-currency")]
+    pub statement_currency: Option<String>,
+
+    // Mark the matched splits as cleared or reconciled, when the closing balance
+    // of the statement equals the reconciled balance of the account
+    #[arg(long = "reconcile", num_args = 0..=1, default_missing_value = "reconciled")]
+    pub reconcile: Option<ReconcileState>,
+
+    // The closing balance to reconcile with, if the statement has none
+    #[arg(long = "closing-balance", allow_hyphen_values = true)]
+    pub closing_balance: Option<Decimal>,
+
     // List extra transactions not found in the external source
     #[arg(long = "list-extra-transactions", short = 'X')]
     pub list_extra_transactions: bool,
@@ -93,6 +148,54 @@ pub struct CorrelateArgs {
     #[arg(long = "verbose", short = 'v')]
     pub verbose: bool,
 
+    // Write into the book, even if it's locked by GnuCash
+    #[arg(long = "force")]
+    pub force: bool,
+
+    // Print what would be written into the book, without writing anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    // Add every missing transaction without asking
+    #[arg(long = "yes", short = 'y')]
+    pub yes: bool,
+
+    // Review the pairs and the missing transactions on a full screen, pair or unpair them by
+    // hand, change their counter account or description, before anything is written
+    #[arg(long = "review", conflicts_with_all = ["yes", "no_input"])]
+    pub review: bool,
+
+    // Never ask, the missing transactions not accepted by the rules are left pending
+    #[arg(long = "no-input")]
+    pub no_input: bool,
+
+    // Without asking, add the missing transactions to the suggested account too, when the
+    // suggestion is confident
+    #[arg(long = "accept-suggested", conflicts_with = "review")]
+    pub accept_suggested: bool,
+
+    // Add the missing transactions passing every rule without asking,
+    // like amount<5000 for the absolute value of the amount, or description~^CARD
+    #[arg(long = "accept-if")]
+    pub accept_if: Vec<String>,
+
+    // The csv report of the pending transactions, by default <input>.pending.csv
+    #[arg(long = "pending")]
+    pub pending: Option<String>,
+
+    // The report of the matched pairs, and the rows missing on either side, as a json, csv or
+    // html file, picked by the extension
+    #[arg(long = "report")]
+    pub report: Option<String>,
+
+    // Rules picking the counter account by the description, other account or category,
+    // the from account is used, when no rule matches
+    #[arg(long = "rules")]
+    pub rules: Option<String>,
+
+    #[command(flatten)]
+    pub csv: CsvArgs,
+
     #[command(flatten)]
     pub account: DefaultAccountParams,
 
@@ -103,6 +206,102 @@ pub struct CorrelateArgs {
     pub fee_account: FeeAccountParams,
 }
 
+#[derive(Args)]
+pub struct CsvArgs {
+    // Field separator of a CSV input
+    #[arg(long = "delimiter")]
+    pub delimiter: Option<char>,
+
+    // Quote character of a CSV input
+    #[arg(long = "quote")]
+    pub quote: Option<char>,
+
+    // Number of rows to skip at the beginning of a CSV input, like the header
+    #[arg(long = "skip-rows")]
+    pub skip_rows: Option<usize>,
+
+    // Character encoding of a CSV input, like utf-8, windows-1250 or latin2
+    #[arg(long = "encoding")]
+    pub encoding: Option<String>,
+
+    // Amounts use decimal comma, like 1.234,50
+    #[arg(long = "decimal-comma")]
+    pub decimal_comma: bool,
+
+    // Column mapping for the csv format, like date=0,booking_date=1,amount=3,description=5,
+    // the optional balance column has the running balance after each row
+    #[arg(long = "columns")]
+    pub columns: Option<String>,
+
+    // Date format of the date columns for the csv format, like %Y.%m.%d.
+    #[arg(long = "date-format")]
+    pub date_format: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    // The format of the exported file, only qif is supported
+    #[arg(long = "format", default_value = "qif")]
+    pub format: String,
+
+    // The file to write, by default the standard output
+    #[arg(long = "output", short = 'O')]
+    pub output: Option<String>,
+
+    // Splits before the given date in yyyy-mm-dd format
+    #[arg(long = "before", short = 'b')]
+    pub before: Option<String>,
+
+    // Splits after the given date in yyyy-mm-dd format
+    #[arg(long = "after", short = 'f')]
+    pub after: Option<String>,
+
+    #[command(flatten)]
+    pub account: DefaultAccountParams,
+}
+
+#[derive(Args)]
+pub struct DuplicatesArgs {
+    // The maximum number of days between the duplicated transactions
+    #[arg(long = "max-days", default_value_t = 3)]
+    pub max_days: u32,
+
+    // How similar the descriptions should be, in percent
+    #[arg(long = "min-similarity", default_value_t = 60)]
+    pub min_similarity: u32,
+
+    // Merge every group without asking
+    #[arg(long = "auto")]
+    pub auto: bool,
+
+    // Write into the book, even if it's locked by GnuCash
+    #[arg(long = "force")]
+    pub force: bool,
+
+    // Print what would be written into the book, without writing anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+
+    // The account to scan, the whole book is scanned without it
+    #[command(flatten)]
+    pub account: DefaultAccountParams,
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    // The id of the run to revert, by default the last one
+    #[arg(long = "run")]
+    pub run: Option<i32>,
+
+    // Write into the book, even if it's locked by GnuCash
+    #[arg(long = "force")]
+    pub force: bool,
+
+    // Print what would be written into the book, without writing anything
+    #[arg(long = "dry-run")]
+    pub dry_run: bool,
+}
+
 #[derive(Args)]
 pub struct CommoditiesArgs {
     // List only a given type of commodities
@@ -1,62 +1,161 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::ops::Bound::Included;
+use std::path::Path;
 
-use anyhow::Result;
-use chrono::{Duration, Local, NaiveDate};
+use anyhow::{Context, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
 use console::{Key, Term, style};
 use diesel::prelude::*;
 use guid_create::GUID;
+use rust_decimal::Decimal;
 
+use crate::acceptance::{Acceptance, Decision, write_pending_report};
+use crate::assignment::{Edge, min_cost_assignment, subset_sum};
+use crate::balance::verify_balances;
+use crate::counter_rules::CounterAccountRules;
 use crate::dbmodifier::{NewSplit, NewTransaction};
 use crate::external_models::{
-    ExternalTransaction, ExternalTransactionList, Matching, SheetDefinition, SheetParser,
-    TransactionPairing,
+    AmountTolerance, Balance, DateWindow, ExternalTransaction, ExternalTransactionList, Matching,
+    SheetDefinition, StatementFormat, TransactionPairing,
 };
-use crate::models::{Account, Split, Transaction};
-use crate::query::accounts::AccountQuery;
+use crate::history::{ImportHistory, identities, record_import, record_imports};
+use crate::journal::{Changes, Journal};
+use crate::lock::with_book_lock;
+use crate::models::{Account, Commodities, Split, Transaction};
+use crate::query::accounts::{AccountQuery, accounts_with_full_names};
 use crate::query::currencies::CommoditiesQuery;
+use crate::query::prices::PriceList;
 use crate::query::transactions::TransactionQuery;
+use crate::readers::csv::CsvOptions;
+use crate::reconcile::{ReconcileState, reconcile, reconciled_balance};
+use crate::report::{BookRow, CorrelationReport, StatementRow};
+use crate::review::{Review, ReviewedRow};
+use crate::suggestion::{AUTO_CONFIDENCE, AccountSuggester, Suggestion};
 use crate::utils::{format_guid, get_value_or_empty, to_string};
 
 pub struct CorrelationCommand {
     pub input_file: String,
     pub sheet_name: Option<String>,
+    pub csv_options: Option<CsvOptions>,
     pub matching: Matching,
+    pub window: DateWindow,
+    pub tolerance: AmountTolerance,
+    // the currency of the statement, when it differs from the commodity of the account
+    pub statement_currency: Option<String>,
+    // mark the matched splits, if the closing balance equals the reconciled balance
+    pub reconcile: Option<ReconcileState>,
+    // instead of the closing balance of the statement
+    pub closing_balance: Option<Decimal>,
     pub verbose: bool,
     pub list_extra_transactions: bool,
+    pub force: bool,
+    pub dry_run: bool,
+    pub acceptance: Acceptance,
+    pub pending_file: String,
+    // review the pairs and the missing rows on a full screen, before anything is written
+    pub review: bool,
+    pub report_file: Option<String>,
+    pub rules_file: Option<String>,
     pub account_query: AccountQuery,
     pub counterparty_account_query: AccountQuery,
     pub fee_account_query: AccountQuery,
 }
 
+// Every day between the dates costs more than the most different descriptions
+const DAY_COST: u32 = 100;
+// An other pair, which costs less more than this, is just as likely
+const AMBIGUITY_MARGIN: u32 = 10;
+// An amount within the tolerance is worse than an exact one, but better than a day more
+const INEXACT_AMOUNT_COST: u32 = DAY_COST / 2;
+
+// The most rows, which can add up to one transaction, and the closest ones checked for it
+const MAX_GROUP_SIZE: usize = 4;
+const MAX_GROUP_CANDIDATES: usize = 12;
+
+// Statement rows and booked transactions, which are matched by their sum
+struct Grouping<'a> {
+    externals: Vec<ExternalTransaction>,
+    pairings: Vec<&'a TransactionPairing>,
+}
+
+// Why a statement row and a booked transaction can be paired
+#[derive(Debug, Clone, Copy)]
+struct PairScore {
+    // the booked date minus the statement date
+    days: i64,
+    // of the descriptions, between 0 and 1
+    similarity: f64,
+    // the booked amount minus the amount of the statement row, within the tolerance
+    amount_difference: Decimal,
+}
+
+impl PairScore {
+    fn cost(&self) -> u32 {
+        let dissimilarity = ((1.0 - self.similarity) * f64::from(DAY_COST - 1)).round() as u32;
+        let inexact = if self.amount_difference.is_zero() {
+            0
+        } else {
+            INEXACT_AMOUNT_COST
+        };
+        self.days.unsigned_abs() as u32 * DAY_COST + dissimilarity + inexact
+    }
+}
+
+impl fmt::Display for PairScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.days {
+            0 => f.write_str("booked on the same day")?,
+            days if days > 0 => write!(f, "booked {} days later", days)?,
+            days => write!(f, "booked {} days earlier", -days)?,
+        }
+        write!(f, ", description {:.0}% similar", self.similarity * 100.0)?;
+        if !self.amount_difference.is_zero() {
+            write!(f, ", amount differs by {}", self.amount_difference)?;
+        }
+        Ok(())
+    }
+}
+
 struct TransactionCorrelator {
     external_transactions: ExternalTransactionList,
     account: String,
     matching: Matching,
+    window: DateWindow,
+    tolerance: AmountTolerance,
     transaction_map: BTreeMap<NaiveDate, Vec<TransactionPairing>>,
+    // the identity of every statement row, and the rows imported by an earlier run
+    identities: Vec<String>,
+    history: ImportHistory,
+    // the imported rows, whose transaction is moved to an other account since
+    skipped: Vec<usize>,
     verbose: bool,
 }
 
 impl TransactionCorrelator {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        input_file: &str,
-        sheet_name: Option<String>,
+        external_transactions: ExternalTransactionList,
         account: String,
         matching: Matching,
+        window: DateWindow,
+        tolerance: AmountTolerance,
+        identities: Vec<String>,
+        history: ImportHistory,
         verbose: bool,
-        format: &dyn SheetParser,
-        term: &Term,
-    ) -> Result<Self> {
-        let mut sheet_definition = SheetDefinition::new(input_file)?;
-
-        let external_transactions = sheet_definition.load(sheet_name, matching, format, term)?;
-        Ok(TransactionCorrelator {
+    ) -> Self {
+        TransactionCorrelator {
             external_transactions,
             account,
             matching,
+            window,
+            tolerance,
             transaction_map: BTreeMap::new(),
+            identities,
+            history,
+            skipped: Vec::new(),
             verbose,
-        })
+        }
     }
 
     fn load_from_database(&self, connection: &mut SqliteConnection) -> Vec<(Split, Transaction)> {
@@ -118,75 +217,337 @@ impl TransactionCorrelator {
             .collect()
     }
 
-    pub fn match_transactions(&mut self) -> Vec<ExternalTransaction> {
-        let mut working_set = self.external_transactions.0.clone();
+    // The booked transactions, which can be paired with a statement row, ordered by date
+    fn unmatched_pairings(&self) -> Vec<(NaiveDate, &TransactionPairing)> {
+        self.transaction_map
+            .iter()
+            .flat_map(|(date, list)| list.iter().map(move |pairing| (*date, pairing)))
+            .filter(|(_, pairing)| pairing.is_not_matched())
+            .collect()
+    }
+
+    // The possible pairs of the statement rows and the booked transactions with the same
+    // amount within the date window
+    fn candidate_pairs(
+        &self,
+        pairings: &[(NaiveDate, &TransactionPairing)],
+        matched: &[bool],
+    ) -> Vec<(Edge, PairScore)> {
+        let mut candidates = Vec::new();
+        for (row, external_transaction) in self.external_transactions.0.iter().enumerate() {
+            let (false, Some(ext_date), Some(ext_amount)) = (
+                matched[row],
+                external_transaction.get_matching_date(self.matching),
+                external_transaction.get_amount(),
+            ) else {
+                continue;
+            };
+            let from = ext_date - Duration::days(self.window.days_before.into());
+            let to = ext_date + Duration::days(self.window.days_after.into());
+            let start = pairings.partition_point(|(date, _)| *date < from);
+            for (column, (date, pairing)) in pairings.iter().enumerate().skip(start) {
+                if *date > to {
+                    break;
+                }
+                if pairing.is_close_amount(ext_amount, &self.tolerance) {
+                    let score = PairScore {
+                        days: (*date - ext_date).num_days(),
+                        similarity: pairing.similarity_to(external_transaction),
+                        amount_difference: pairing.amount() - ext_amount,
+                    };
+                    let edge = Edge {
+                        row,
+                        column,
+                        cost: score.cost(),
+                    };
+                    candidates.push((edge, score));
+                }
+            }
+        }
+        candidates
+    }
+
+    // Pairs the rows imported by an earlier run with their transaction, the rows whose
+    // transaction is not in this account any more are skipped, returns the skipped rows
+    fn match_imported(&self, matched: &mut [bool]) -> Vec<usize> {
+        let by_guid: HashMap<&str, &TransactionPairing> = self
+            .transaction_map
+            .values()
+            .flatten()
+            .map(|pairing| (pairing.split().tx_guid.as_str(), pairing))
+            .collect();
+        let mut skipped = Vec::new();
+        for (row, identity) in self.identities.iter().enumerate() {
+            let Some(tx_guid) = self.history.transaction_of(identity) else {
+                continue;
+            };
+            match by_guid.get(tx_guid) {
+                Some(pairing) => pairing.pair_with(row, &self.external_transactions.0[row]),
+                None => skipped.push(row),
+            }
+            matched[row] = true;
+        }
+        skipped
+    }
+
+    // Pairs the statement rows with the booked transactions, so the most rows are paired,
+    // the dates are the closest and the descriptions are the most similar overall,
+    // returns the indexes of the unmatched statement rows
+    pub fn match_transactions(&mut self) -> Vec<usize> {
+        let externals = &self.external_transactions.0;
         if self.verbose {
-            println!("Starting with {} transactions", &working_set.len());
+            println!("Starting with {} transactions", externals.len());
         }
-        working_set = self.match_transactions_with_delta_day(0, &working_set);
+        let mut matched = vec![false; externals.len()];
+        let skipped = self.match_imported(&mut matched);
         if self.verbose {
             println!(
-                "After matching with 0, {} transaction remained as unmatched",
-                &working_set.len()
+                "{} rows are known from the earlier imports",
+                matched.iter().filter(|matched| **matched).count()
             );
         }
-        let mut delta_day = 0;
-        while !&working_set.is_empty() && delta_day < 10 {
-            delta_day += 1;
-            working_set = self.match_transactions_with_delta_day(delta_day, &working_set);
-            working_set = self.match_transactions_with_delta_day(-delta_day, &working_set);
-            if self.verbose {
-                println!(
-                    "After matching with {}, {} transaction remained as unmatched",
-                    &delta_day,
-                    &working_set.len()
-                );
+        let pairings = self.unmatched_pairings();
+        let candidates = self.candidate_pairs(&pairings, &matched);
+        if self.verbose {
+            println!("Found {} possible pairs", candidates.len());
+        }
+        let edges: Vec<Edge> = candidates.iter().map(|(edge, _)| *edge).collect();
+        let assignment = min_cost_assignment(&edges, externals.len(), pairings.len());
+        for chosen in &assignment {
+            let pairing = pairings[chosen.column].1;
+            pairing.pair_with(chosen.row, &externals[chosen.row]);
+            matched[chosen.row] = true;
+            // an other row or booked transaction, which is not clearly worse, could replace it
+            let alternatives = edges
+                .iter()
+                .filter(|edge| (edge.row == chosen.row) != (edge.column == chosen.column))
+                .filter(|edge| edge.cost < chosen.cost + AMBIGUITY_MARGIN)
+                .count();
+            if alternatives > 0 {
+                pairing.mark_ambiguous();
+            }
+            if self.verbose
+                && let Some((_, score)) = candidates.iter().find(|(edge, _)| edge == chosen)
+            {
+                println!(" - {}", externals[chosen.row]);
+                println!("   = {}", pairing);
+                println!("   {}, {} other candidates are close", score, alternatives);
             }
         }
-        working_set
+        let groups = self.match_groups(&pairings, &mut matched);
+        if self.verbose {
+            println!(
+                "After matching, {} transaction remained as unmatched, {} groups are matched by their sum",
+                matched.iter().filter(|matched| !**matched).count(),
+                groups
+            );
+        }
+        self.skipped = skipped;
+        (0..matched.len()).filter(|row| !matched[*row]).collect()
+    }
+
+    fn is_within_window(&self, external_date: NaiveDate, booked_date: NaiveDate) -> bool {
+        let days = (booked_date - external_date).num_days();
+        -i64::from(self.window.days_before) <= days && days <= i64::from(self.window.days_after)
     }
 
-    // return the unmatched transactions
-    pub fn match_transactions_with_delta_day(
+    // Pairs the leftovers, where more statement rows add up to one booked transaction, like
+    // a card payment and its separate FX fee, or one statement row to more booked transactions.
+    // Returns the number of the groups.
+    fn match_groups(
         &self,
-        delta_day: i64,
-        transactions: &[ExternalTransaction],
-    ) -> Vec<ExternalTransaction> {
-        let mut result = Vec::new();
-        for external_transaction in transactions {
-            if self
-                .add_transaction(delta_day, external_transaction)
-                .is_none()
-            {
-                result.push(external_transaction.clone());
+        pairings: &[(NaiveDate, &TransactionPairing)],
+        matched: &mut [bool],
+    ) -> usize {
+        let externals = &self.external_transactions.0;
+        let mut groups = 0;
+        for (booked_date, pairing) in pairings {
+            if !pairing.is_not_matched() {
+                continue;
+            }
+            let mut candidates: Vec<(i64, usize, Decimal)> = externals
+                .iter()
+                .enumerate()
+                .filter(|(row, _)| !matched[*row])
+                .filter_map(|(row, external_transaction)| {
+                    let date = external_transaction.get_matching_date(self.matching)?;
+                    let amount = external_transaction.get_amount()?;
+                    self.is_within_window(date, *booked_date)
+                        .then(|| ((*booked_date - date).num_days().abs(), row, amount))
+                })
+                .collect();
+            candidates.sort_by_key(|(days, row, _)| (*days, *row));
+            candidates.truncate(MAX_GROUP_CANDIDATES);
+            let target = pairing.amount();
+            candidates.retain(|(_, _, amount)| is_same_sign(*amount, target));
+            let amounts: Vec<Decimal> = candidates.iter().map(|(_, _, amount)| *amount).collect();
+            if let Some(subset) = subset_sum(&amounts, MAX_GROUP_SIZE, |sum| {
+                pairing.is_close_amount(sum, &self.tolerance)
+            }) {
+                for idx in subset.indexes {
+                    let row = candidates[idx].1;
+                    pairing.pair_with(row, &externals[row]);
+                    matched[row] = true;
+                }
+                if subset.ambiguous {
+                    pairing.mark_ambiguous();
+                }
+                pairing.set_group(groups);
+                groups += 1;
+            }
+        }
+        for (row, external_transaction) in externals.iter().enumerate() {
+            let (false, Some(date), Some(amount)) = (
+                matched[row],
+                external_transaction.get_matching_date(self.matching),
+                external_transaction.get_amount(),
+            ) else {
+                continue;
+            };
+            let mut candidates: Vec<(i64, &TransactionPairing)> = pairings
+                .iter()
+                .filter(|(booked_date, pairing)| {
+                    pairing.is_not_matched()
+                        && is_same_sign(pairing.amount(), amount)
+                        && self.is_within_window(date, *booked_date)
+                })
+                .map(|(booked_date, pairing)| ((*booked_date - date).num_days().abs(), *pairing))
+                .collect();
+            candidates.sort_by_key(|(days, _)| *days);
+            candidates.truncate(MAX_GROUP_CANDIDATES);
+            let amounts: Vec<Decimal> = candidates
+                .iter()
+                .map(|(_, pairing)| pairing.amount())
+                .collect();
+            if let Some(subset) = subset_sum(&amounts, MAX_GROUP_SIZE, |sum| {
+                self.tolerance.accepts(sum, amount)
+            }) {
+                for idx in subset.indexes {
+                    let pairing = candidates[idx].1;
+                    pairing.pair_with(row, external_transaction);
+                    pairing.set_group(groups);
+                    if subset.ambiguous {
+                        pairing.mark_ambiguous();
+                    }
+                }
+                matched[row] = true;
+                groups += 1;
             }
         }
-        result
+        groups
     }
 
-    fn add_transaction(
-        &self,
-        delta_day: i64,
-        external_transaction: &ExternalTransaction,
-    ) -> Option<&TransactionPairing> {
-        if let Some(ext_date) = external_transaction.get_matching_date(self.matching) {
-            let actual_date = match delta_day {
-                0 => ext_date,
-                _ => ext_date
-                    .checked_add_signed(Duration::days(delta_day))
-                    .unwrap(),
-            };
-            if let Some(ext_amount) = external_transaction.get_amount()
-                && let Some(list) = self.transaction_map.get(&actual_date)
-                && let Some(tr_pairing) = list
-                    .iter()
-                    .find(|&x| x.is_equal_amount(ext_amount) && x.is_not_matched())
-            {
-                tr_pairing.pair_with(external_transaction);
-                return Some(tr_pairing);
+    fn get_groups(&self) -> Vec<Grouping<'_>> {
+        let mut groups: BTreeMap<usize, Vec<&TransactionPairing>> = BTreeMap::new();
+        for pairing in self.transaction_map.values().flatten() {
+            if let Some(group) = pairing.group() {
+                groups.entry(group).or_default().push(pairing);
             }
         }
-        None
+        groups
+            .into_values()
+            .map(|pairings| {
+                let rows: BTreeSet<usize> = pairings.iter().flat_map(|p| p.rows()).collect();
+                Grouping {
+                    externals: rows
+                        .into_iter()
+                        .map(|row| self.external_transactions.0[row].clone())
+                        .collect(),
+                    pairings,
+                }
+            })
+            .collect()
+    }
+
+    // The booked transactions paired with a statement row, until the given day
+    fn get_matched(&self, until: NaiveDate) -> Vec<&TransactionPairing> {
+        self.transaction_map
+            .range(..=until)
+            .flat_map(|(_, list)| list)
+            .filter(|pairing| !pairing.is_not_matched())
+            .collect()
+    }
+
+    // The identities of the rows paired with one booked transaction, and the guid of that
+    // transaction, which are not in the import history yet
+    fn new_matches(&self) -> Vec<(String, String)> {
+        let mut transactions: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for pairing in self.transaction_map.values().flatten() {
+            for row in pairing.rows() {
+                transactions
+                    .entry(row)
+                    .or_default()
+                    .push(pairing.transaction().guid.as_str());
+            }
+        }
+        transactions
+            .into_iter()
+            .filter_map(|(row, tx_guids)| match tx_guids.as_slice() {
+                [tx_guid] => Some((self.identities[row].as_str(), *tx_guid)),
+                // a row of a group, which is paired with more transactions
+                _ => None,
+            })
+            .filter(|(identity, tx_guid)| self.history.transaction_of(identity) != Some(tx_guid))
+            .map(|(identity, tx_guid)| (identity.to_owned(), tx_guid.to_owned()))
+            .collect()
+    }
+
+    // The statement rows with the given indexes, and their identities
+    fn rows_of(&self, rows: &[usize]) -> (Vec<ExternalTransaction>, Vec<String>) {
+        rows.iter()
+            .map(|row| {
+                (
+                    self.external_transactions.0[*row].clone(),
+                    self.identities[*row].clone(),
+                )
+            })
+            .unzip()
+    }
+
+    // The booked transactions of the review, the paired ones, and the unpaired ones within
+    // the dates of the statement
+    fn get_reviewable(&self) -> Vec<&TransactionPairing> {
+        let unmatched = self.get_unmatched();
+        self.transaction_map
+            .values()
+            .flatten()
+            .filter(|pairing| {
+                !pairing.is_not_matched()
+                    || unmatched.iter().any(|other| std::ptr::eq(*other, *pairing))
+            })
+            .collect()
+    }
+
+    // Every matched pair, and the rows found only on one side
+    fn build_report(&self, account: &Account, unmatched_rows: &[usize]) -> CorrelationReport {
+        let mut report = CorrelationReport {
+            account: account.name.clone(),
+            from: to_string(self.get_min_date()),
+            to: to_string(self.get_max_date()),
+            ..Default::default()
+        };
+        for pairing in self.transaction_map.values().flatten() {
+            report.add_pairing(pairing, self.matching);
+        }
+        report.external_only = unmatched_rows
+            .iter()
+            .map(|row| StatementRow::new(&self.external_transactions.0[*row], self.matching))
+            .collect();
+        report.book_only = self.get_unmatched().into_iter().map(BookRow::new).collect();
+        report.imported_earlier = self
+            .skipped
+            .iter()
+            .map(|row| StatementRow::new(&self.external_transactions.0[*row], self.matching))
+            .collect();
+        report
+    }
+
+    fn get_ambiguous(&self) -> Vec<&TransactionPairing> {
+        self.transaction_map
+            .values()
+            .flatten()
+            .filter(|pairing| pairing.is_ambiguous())
+            .collect()
     }
 }
 
@@ -196,34 +557,255 @@ enum Answer {
     No,
     Abort,
     All,
+    Suggested,
 }
 
 struct AddTransactions<'a> {
     connection: &'a mut SqliteConnection,
     unmatched_transactions: &'a [ExternalTransaction],
+    // the identities of the unmatched rows, recorded in the import history
+    identities: &'a [String],
     only_account: &'a Account,
-    counter_account: &'a Account,
+    // used when no rule matches
+    counter_account: Option<&'a Account>,
+    counter_rules: &'a CounterAccountRules,
+    // learned from the history, offered when no rule matches
+    suggester: &'a AccountSuggester,
     fee_account: &'a Option<Account>,
+    acceptance: &'a Acceptance,
+    // on a dry run, the new transactions are only printed, nothing is written
+    changes: Changes<'a>,
     term: &'a Term,
 }
 
 impl CorrelationCommand {
+    // The counter account and the description of every statement row, as the prompt would
+    // offer them, by the rules, the given account and the suggestions
+    fn review_proposals<'a>(
+        &self,
+        transactions: &[ExternalTransaction],
+        counter_account: Option<&'a Account>,
+        counter_rules: &'a CounterAccountRules,
+        suggester: &'a AccountSuggester,
+    ) -> Vec<ReviewedRow<'a>> {
+        transactions
+            .iter()
+            .enumerate()
+            .map(|(row, transaction)| {
+                let rule = counter_rules.find(transaction);
+                let counter = match rule {
+                    Some(rule) => Some(&rule.account),
+                    None => counter_account.or_else(|| {
+                        suggester
+                            .suggest(transaction)
+                            .map(|suggestion| suggestion.account)
+                    }),
+                };
+                ReviewedRow {
+                    row,
+                    counter,
+                    description: rule
+                        .and_then(|rule| rule.description.clone())
+                        .or_else(|| transaction.get_description_or_category())
+                        .unwrap_or_default(),
+                    add: counter.is_some()
+                        && self.acceptance.decide(transaction) != Decision::Pending,
+                }
+            })
+            .collect()
+    }
+
+    fn report_pending(&self, pending: &[ExternalTransaction], term: &Term) -> Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        if self.dry_run {
+            term.write_line(&format!(
+                "{} transactions would be left pending:",
+                style(pending.len()).red()
+            ))?;
+            for transaction in pending {
+                term.write_line(&format!(" - {}", transaction))?;
+            }
+        } else {
+            write_pending_report(&self.pending_file, pending)?;
+            term.write_line(&format!(
+                "{} transactions are left pending, see {}",
+                style(pending.len()).red(),
+                style(&self.pending_file).blue()
+            ))?;
+        }
+        Ok(())
+    }
+
+    // Writes the report, if a report file is given
+    fn write_report(&self, report: &CorrelationReport, term: &Term) -> Result<()> {
+        if let Some(file) = &self.report_file {
+            report.write(file)?;
+            term.write_line(&format!("Report written to {}", style(file).cyan()))?;
+        }
+        Ok(())
+    }
+
+    // Remembers the matched rows in the import history, so an overlapping statement is
+    // recognized by them
+    fn remember_matched(
+        &self,
+        connection: &mut SqliteConnection,
+        account: &Account,
+        matched: &[(String, String)],
+        term: &Term,
+    ) -> Result<()> {
+        if matched.is_empty() {
+            return Ok(());
+        }
+        if self.dry_run {
+            term.write_line(&format!(
+                "Dry run, {} matched rows would be remembered",
+                style(matched.len()).cyan()
+            ))?;
+            return Ok(());
+        }
+        with_book_lock(connection, self.force, term, |connection| {
+            record_imports(connection, &account.guid, matched)
+        })
+        .context("Unable to remember the matched rows")?;
+        term.write_line(&format!(
+            "Remembered {} matched rows",
+            style(matched.len()).cyan()
+        ))?;
+        Ok(())
+    }
+
+    // Marks the matched splits until the closing balance, if the balance of the reconciled
+    // splits equals to it
+    fn reconcile_matched(
+        &self,
+        connection: &mut SqliteConnection,
+        correlator: &TransactionCorrelator,
+        account: &Account,
+        state: ReconcileState,
+        term: &Term,
+    ) -> Result<()> {
+        let closing = match self.closing_balance {
+            Some(amount) => Some(Balance { date: None, amount }),
+            None => correlator.external_transactions.3.closing.clone(),
+        };
+        let Some(closing) = closing else {
+            term.write_line(&format!(
+                "The statement has no closing balance, use {} to reconcile",
+                style("--closing-balance").red()
+            ))?;
+            return Err(anyhow!("The closing balance is required to reconcile!"));
+        };
+        let until = closing
+            .date
+            .or(correlator.get_max_date())
+            .context("The statement has no dates!")?;
+        let splits: Vec<&Split> = correlator
+            .get_matched(until)
+            .into_iter()
+            .map(|pairing| pairing.split())
+            .collect();
+        let marked: HashSet<&str> = splits.iter().map(|split| split.guid.as_str()).collect();
+        let balance = reconciled_balance(connection, account, &marked, until);
+        if balance != closing.amount {
+            term.write_line(&format!(
+                "The reconciled balance {} differs from the closing balance {}, nothing is reconciled!",
+                style(balance).red(),
+                style(&closing).red()
+            ))?;
+            return Err(anyhow!(
+                "The reconciled balance {} differs from the closing balance {}!",
+                balance,
+                closing.amount
+            ));
+        }
+        if self.dry_run {
+            reconcile(connection, &splits, state, until, Changes::DryRun, term)?;
+        } else {
+            with_book_lock(connection, self.force, term, |connection| {
+                let journal = Journal::new("correlate --reconcile", term);
+                reconcile(
+                    connection,
+                    &splits,
+                    state,
+                    until,
+                    Changes::Write(&journal),
+                    term,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn load_external_transactions(
+        &self,
+        term: &Term,
+        format: &StatementFormat,
+    ) -> Result<ExternalTransactionList> {
+        match format {
+            StatementFormat::Sheet(parser) => {
+                let mut sheet_definition = match &self.csv_options {
+                    Some(options) => SheetDefinition::from_csv(&self.input_file, options)?,
+                    None => SheetDefinition::new(&self.input_file)?,
+                };
+                sheet_definition.load(
+                    self.sheet_name.clone(),
+                    self.matching,
+                    parser.as_ref(),
+                    term,
+                )
+            }
+            StatementFormat::Statement(parser) => ExternalTransactionList::load_statement(
+                &self.input_file,
+                self.matching,
+                parser.as_ref(),
+                term,
+            ),
+        }
+    }
+
     pub fn execute(
         &mut self,
         connection: &mut SqliteConnection,
         term: &Term,
-        format: &dyn SheetParser,
+        format: &StatementFormat,
     ) -> Result<usize> {
         if let Some(only_account) = self.account_query.get_one(connection, true) {
+            let mut external_transactions = self.load_external_transactions(term, format)?;
+            // the rows are remembered as they are in the statement, not as they are converted
+            let identities = identities(&external_transactions.0);
+            if let Some(currency) = &self.statement_currency {
+                convert_currency(
+                    connection,
+                    &mut external_transactions,
+                    currency,
+                    &only_account,
+                    self.matching,
+                    term,
+                )?;
+            }
+            if !external_transactions.3.is_empty() {
+                verify_balances(
+                    connection,
+                    &only_account,
+                    &external_transactions,
+                    self.matching,
+                    term,
+                )?;
+            }
+            let history = ImportHistory::load(connection, &only_account.guid)?;
             let mut correlator = TransactionCorrelator::new(
-                &self.input_file,
-                self.sheet_name.clone(),
+                external_transactions,
                 only_account.guid.clone(),
                 self.matching,
+                self.window,
+                self.tolerance,
+                identities,
+                history,
                 self.verbose,
-                format,
-                term,
-            )?;
+            );
             correlator.build_mapping(connection);
 
             term.write_line(&format!(
@@ -232,7 +814,19 @@ impl CorrelationCommand {
                 style(to_string(correlator.get_max_date())).cyan()
             ))?;
 
-            let unmatched_transactions = correlator.match_transactions();
+            let mut unmatched_rows = correlator.match_transactions();
+            let (unmatched_transactions, _) = correlator.rows_of(&unmatched_rows);
+            if !correlator.skipped.is_empty() {
+                term.write_line(&format!(
+                    "Skipping {} rows imported earlier, their transactions are in other accounts now",
+                    style(correlator.skipped.len()).yellow()
+                ))?;
+                if self.verbose {
+                    for row in &correlator.skipped {
+                        println!(" - {}", correlator.external_transactions.0[*row]);
+                    }
+                }
+            }
             term.write_line(&format!(
                 "Missing {} record from the internal database:",
                 style(&unmatched_transactions.len()).red()
@@ -256,20 +850,152 @@ impl CorrelationCommand {
                 }
             }
 
-            if !unmatched_transactions.is_empty() {
+            let ambiguous = correlator.get_ambiguous();
+            if !ambiguous.is_empty() {
+                term.write_line(&format!(
+                    "{} pairs are ambiguous, an other pairing would be just as close:",
+                    style(ambiguous.len()).yellow()
+                ))?;
+                for pairing in &ambiguous {
+                    for external in pairing.externals() {
+                        println!(" - {}", external);
+                    }
+                    println!("   = {}", pairing);
+                }
+            }
+
+            let groups = correlator.get_groups();
+            if !groups.is_empty() {
+                term.write_line(&format!(
+                    "{} groups are matched by their sum:",
+                    style(groups.len()).cyan()
+                ))?;
+                for group in &groups {
+                    for external in &group.externals {
+                        println!(" - {}", external);
+                    }
+                    for pairing in &group.pairings {
+                        println!("   = {}", pairing);
+                    }
+                }
+            }
+
+            // the review can change the pairs, they are remembered with the rows it adds
+            if !self.review {
+                self.remember_matched(connection, &only_account, &correlator.new_matches(), term)?;
+            }
+            if !unmatched_transactions.is_empty() || self.review {
                 let fee_account = self.fee_account_query.get_one(connection, false);
-                if let Some(counter_account) =
-                    self.counterparty_account_query.get_one(connection, true)
+                let counter_rules = match &self.rules_file {
+                    Some(file) => CounterAccountRules::load(connection, Path::new(file))?,
+                    None => CounterAccountRules::default(),
+                };
+                let suggester = AccountSuggester::train(connection, &only_account)?;
+                term.write_line(&format!(
+                    "Learned the counter accounts from {} transactions",
+                    style(suggester.len()).cyan()
+                ))?;
+                let counter_account = self.counterparty_account_query.get_one(
+                    connection,
+                    !self.review && counter_rules.rules.is_empty() && suggester.is_empty(),
+                );
+                let accounts: Vec<(String, Account)> = if self.review {
+                    accounts_with_full_names(connection)
+                        .into_iter()
+                        .filter(|(_, account)| {
+                            account.commodity_guid == only_account.commodity_guid
+                                && account.guid != only_account.guid
+                                && account.placeholder.unwrap_or_default() == 0
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                let reviewed = if self.review {
+                    let proposals = self.review_proposals(
+                        &correlator.external_transactions.0,
+                        counter_account.as_ref(),
+                        &counter_rules,
+                        &suggester,
+                    );
+                    let review = Review::new(
+                        format!(
+                            "Reviewing {} between {} and {}",
+                            only_account.name,
+                            to_string(correlator.get_min_date()),
+                            to_string(correlator.get_max_date())
+                        ),
+                        &correlator.external_transactions.0,
+                        correlator.get_reviewable(),
+                        &accounts,
+                        proposals,
+                        &unmatched_rows,
+                    );
+                    // the review changes the pairs in place, a quit one reports them as before
+                    let unreviewed = correlator.build_report(&only_account, &unmatched_rows);
+                    let Some(outcome) = review.run(term)? else {
+                        term.write_line("The review is quit, nothing is written")?;
+                        self.write_report(&unreviewed, term)?;
+                        return Ok(unmatched_rows.len());
+                    };
+                    unmatched_rows = outcome.missing.iter().map(|row| row.row).collect();
+                    Some(outcome)
+                } else {
+                    None
+                };
+                let (unmatched_transactions, unmatched_identities) =
+                    correlator.rows_of(&unmatched_rows);
+                let matched = correlator.new_matches();
+                if reviewed.is_some()
+                    || counter_account.is_some()
+                    || !counter_rules.rules.is_empty()
+                    || !suggester.is_empty()
                 {
-                    let mut add_transactions = AddTransactions {
-                        connection,
-                        unmatched_transactions: &unmatched_transactions,
-                        only_account: &only_account,
-                        counter_account: &counter_account,
-                        fee_account: &fee_account,
-                        term,
+                    let pending = if self.dry_run {
+                        let mut add_transactions = AddTransactions {
+                            connection,
+                            unmatched_transactions: &unmatched_transactions,
+                            identities: &unmatched_identities,
+                            only_account: &only_account,
+                            counter_account: counter_account.as_ref(),
+                            counter_rules: &counter_rules,
+                            suggester: &suggester,
+                            fee_account: &fee_account,
+                            acceptance: &self.acceptance,
+                            changes: Changes::DryRun,
+                            term,
+                        };
+                        match &reviewed {
+                            Some(outcome) => {
+                                add_transactions.commit_review(&outcome.missing, &matched)?
+                            }
+                            None => add_transactions.try_to_fix()?,
+                        }
+                    } else {
+                        with_book_lock(connection, self.force, term, |connection| {
+                            let journal = Journal::new("correlate", term);
+                            let mut add_transactions = AddTransactions {
+                                connection,
+                                unmatched_transactions: &unmatched_transactions,
+                                identities: &unmatched_identities,
+                                only_account: &only_account,
+                                counter_account: counter_account.as_ref(),
+                                counter_rules: &counter_rules,
+                                suggester: &suggester,
+                                fee_account: &fee_account,
+                                acceptance: &self.acceptance,
+                                changes: Changes::Write(&journal),
+                                term,
+                            };
+                            match &reviewed {
+                                Some(outcome) => {
+                                    add_transactions.commit_review(&outcome.missing, &matched)
+                                }
+                                None => add_transactions.try_to_fix(),
+                            }
+                        })?
                     };
-                    add_transactions.try_to_fix()?;
+                    self.report_pending(&pending, term)?;
                 } else {
                     term.write_line(&format!(
                         "Unable to fix, as {} is not specified exactly!",
@@ -285,58 +1011,297 @@ impl CorrelationCommand {
                     style("ok.").green()
                 ))?;
             }
-            Ok(unmatched_transactions.len())
+            self.write_report(
+                &correlator.build_report(&only_account, &unmatched_rows),
+                term,
+            )?;
+            if let Some(state) = self.reconcile {
+                self.reconcile_matched(connection, &correlator, &only_account, state, term)?;
+            }
+            Ok(unmatched_rows.len())
         } else {
             Err(anyhow!("Account is not specified exactly!"))
         }
     }
 }
 
-impl AddTransactions<'_> {
-    fn try_to_fix(&mut self) -> Result<()> {
-        if self.only_account.commodity_guid != self.counter_account.commodity_guid {
+// The parts of a payment split into more rows or transactions have the sign of the payment,
+// a refund, or an empty row is not part of it
+fn is_same_sign(amount: Decimal, target: Decimal) -> bool {
+    !amount.is_zero() && amount.is_sign_negative() == target.is_sign_negative()
+}
+
+// Converts the amounts of the statement rows to the commodity of the account, with the exchange
+// rate of the prices table at the matching date, or the closest one before
+fn convert_currency(
+    connection: &mut SqliteConnection,
+    external_transactions: &mut ExternalTransactionList,
+    currency: &str,
+    account: &Account,
+    matching: Matching,
+    term: &Term,
+) -> Result<()> {
+    let statement_commodity = CommoditiesQuery::get_by_mnemonic(connection, currency)
+        .with_context(|| format!("Unknown currency:'{}'!", currency))?;
+    let account_commodity = account
+        .commodity_guid
+        .as_deref()
+        .context("The account has no commodity!")?;
+    if statement_commodity.guid == account_commodity {
+        return Ok(());
+    }
+    let prices = PriceList::load(connection, &statement_commodity.guid, account_commodity)?;
+    ensure!(
+        !prices.is_empty(),
+        "No price between {} and the commodity of {}!",
+        statement_commodity.mnemonic,
+        account
+    );
+    let digits = account.commodity_scu.max(1).ilog10();
+    for transaction in external_transactions.0.iter_mut() {
+        let Some(rate) = transaction
+            .get_matching_date(matching)
+            .and_then(|date| prices.rate_at(date))
+        else {
+            continue;
+        };
+        transaction.amount = transaction
+            .amount
+            .map(|amount| (amount * rate).round_dp(digits));
+        transaction.transaction_fee = transaction
+            .transaction_fee
+            .map(|fee| (fee * rate).round_dp(digits));
+    }
+    let ExternalTransactionList(_, first_date, last_date, balances) = external_transactions;
+    for (balance, fallback_date) in [
+        (balances.opening.as_mut(), *first_date),
+        (balances.closing.as_mut(), *last_date),
+    ] {
+        if let Some(balance) = balance
+            && let Some(rate) = balance
+                .date
+                .or(fallback_date)
+                .and_then(|date| prices.rate_at(date))
+        {
+            balance.amount = (balance.amount * rate).round_dp(digits);
+        }
+    }
+    term.write_line(&format!(
+        "Converted the amounts from {} by the prices table",
+        style(&statement_commodity.mnemonic).cyan()
+    ))?;
+    Ok(())
+}
+
+impl<'a> AddTransactions<'a> {
+    // Adds the missing transactions, returns the ones left pending
+    fn try_to_fix(&mut self) -> Result<Vec<ExternalTransaction>> {
+        let mut sources = Vec::new();
+        if !self.counter_rules.rules.is_empty() {
+            sources.push("the accounts of the rules".to_owned());
+        }
+        if let Some(account) = self.counter_account {
+            sources.push(account.to_string());
+        }
+        if !self.suggester.is_empty() {
+            sources.push("the suggested accounts".to_owned());
+        }
+        let counter_accounts = sources.join(" or ");
+        let dry_run = self.changes.is_dry_run();
+        if dry_run {
             self.term.write_line(&format!(
-                "The two account has different commodities, unable to transfer between: {} - {}",
-                style(self.only_account).red(),
-                style(self.counter_account).red()
+                "Dry run, the following transactions would be created between {} and {}:",
+                counter_accounts, self.only_account
             ))?;
-            return Err(anyhow!(
-                "Unable to fix, the two account has different commodities: {} - {}!",
-                self.only_account,
-                self.counter_account
-            ));
+        } else {
+            self.term.write_line(&format!(
+                "Creating transactions between {} and {}",
+                counter_accounts, self.only_account
+            ))?;
+        }
+        let mut pending = Vec::new();
+        let mut add_rest = false;
+        for (transaction, identity) in self.unmatched_transactions.iter().zip(self.identities) {
+            self.check_fee_configured(transaction)?;
+            let counter = self.counter_account_for(transaction);
+            let suggestion = self.suggestion_for(transaction);
+            match self.acceptance.decide(transaction) {
+                Decision::Pending => pending.push(transaction.clone()),
+                Decision::Ask if !dry_run && !add_rest => {
+                    // the suggestion is offered only if it differs from the given account
+                    let suggested = suggestion.filter(|suggestion| {
+                        counter.is_none_or(|(account, _)| account.guid != suggestion.account.guid)
+                    });
+                    let Some(default) = counter.or(suggested
+                        .as_ref()
+                        .map(|suggestion| (suggestion.account, None)))
+                    else {
+                        self.report_no_counter_account(transaction)?;
+                        pending.push(transaction.clone());
+                        continue;
+                    };
+                    let (target, alternative) = match (counter, &suggested) {
+                        (Some((account, _)), Some(suggestion)) => (
+                            style(account.name.clone()).blue(),
+                            format!("/{}uggested {}", style("S").red(), style(suggestion).blue()),
+                        ),
+                        (_, Some(suggestion)) => {
+                            (style(suggestion.to_string()).blue(), String::new())
+                        }
+                        (Some((account, _)), None) => {
+                            (style(account.name.clone()).blue(), String::new())
+                        }
+                        (None, None) => unreachable!("There is a default account"),
+                    };
+                    self.term.write_line(&format!(
+                        "Adding {} to {} [{}es/{}o/{}bort/a{}l{}]",
+                        style(&transaction).cyan(),
+                        target,
+                        style("Y").red(),
+                        style("N").red(),
+                        style("A").red(),
+                        style("L").red(),
+                        alternative
+                    ))?;
+                    match Answer::get(self.term)? {
+                        Answer::Yes => {
+                            self.add_or_keep(transaction, identity, default, &mut pending)?
+                        }
+                        Answer::No => self
+                            .term
+                            .write_line(&format!("Skipping {}", style(&transaction).magenta()))?,
+                        Answer::Abort => return Ok(pending),
+                        Answer::All => {
+                            add_rest = true;
+                            self.add_or_keep(transaction, identity, default, &mut pending)?;
+                        }
+                        Answer::Suggested => {
+                            let chosen = suggested
+                                .map(|suggestion| (suggestion.account, None))
+                                .unwrap_or(default);
+                            self.add_or_keep(transaction, identity, chosen, &mut pending)?;
+                        }
+                    };
+                }
+                Decision::Accept | Decision::Ask => {
+                    // without asking, a suggestion is used only if it's confident and accepted
+                    let confident = suggestion
+                        .filter(|suggestion| {
+                            self.acceptance.accept_suggested
+                                && suggestion.confidence >= AUTO_CONFIDENCE
+                        })
+                        .map(|suggestion| (suggestion.account, None));
+                    match counter.or(confident) {
+                        Some(counter) => {
+                            self.add_or_keep(transaction, identity, counter, &mut pending)?
+                        }
+                        None => {
+                            self.report_no_counter_account(transaction)?;
+                            pending.push(transaction.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(pending)
+    }
+
+    // Adds the rows of the review with their chosen counter account and description, the
+    // skipped rows are left pending, and remembers the matched rows
+    fn commit_review(
+        &mut self,
+        reviewed: &[ReviewedRow<'a>],
+        matched: &[(String, String)],
+    ) -> Result<Vec<ExternalTransaction>> {
+        // every row is checked, before anything is written
+        let mut planned = Vec::new();
+        let mut pending = Vec::new();
+        for ((transaction, identity), row) in self
+            .unmatched_transactions
+            .iter()
+            .zip(self.identities)
+            .zip(reviewed)
+        {
+            match row.counter {
+                Some(counter) if row.add => {
+                    self.check_counter_account(transaction, counter)?;
+                    self.check_fee_configured(transaction)?;
+                    let plan = self.plan(transaction, (counter, Some(&row.description)))?;
+                    planned.push((transaction, identity, plan));
+                }
+                _ => pending.push(transaction.clone()),
+            }
+        }
+        let Changes::Write(journal) = self.changes else {
+            if !matched.is_empty() {
+                self.term.write_line(&format!(
+                    "Would remember {} matched rows",
+                    style(matched.len()).cyan()
+                ))?;
+            }
+            for (transaction, _, plan) in &planned {
+                self.term
+                    .write_line(&format!("adding {}", style(transaction).red()))?;
+                self.preview(plan)?;
+            }
+            return Ok(pending);
+        };
+        if !planned.is_empty() {
+            journal.begin(self.connection)?;
         }
+        // the review is saved together, or not at all
+        self.connection
+            .transaction::<_, anyhow::Error, _>(|connection| {
+                record_imports(connection, &self.only_account.guid, matched)?;
+                for (transaction, identity, plan) in &planned {
+                    plan.insert(connection, journal, &self.only_account.guid, identity)
+                        .with_context(|| format!("Unable to add {}", transaction))?;
+                }
+                Ok(())
+            })
+            .context("Unable to save the review, nothing is saved")?;
         self.term.write_line(&format!(
-            "Creating transactions between {} and {}",
-            self.counter_account, self.only_account
+            "Remembered {} matched rows, added {} transactions",
+            style(matched.len()).cyan(),
+            style(planned.len()).cyan()
         ))?;
-        for idx in 0..self.unmatched_transactions.len() {
-            let transaction = &self.unmatched_transactions[idx];
-            self.check_fee_configured(transaction)?;
+        Ok(pending)
+    }
+
+    // Adds the row to the chosen counter account, unless the account is in an other commodity,
+    // then the row is left pending
+    fn add_or_keep(
+        &mut self,
+        transaction: &ExternalTransaction,
+        identity: &str,
+        counter: (&'a Account, Option<&String>),
+        pending: &mut Vec<ExternalTransaction>,
+    ) -> Result<()> {
+        if counter.0.commodity_guid != self.only_account.commodity_guid {
             self.term.write_line(&format!(
-                "Adding {} [{}es/{}o/{}bort/a{}l]",
-                style(&transaction).cyan(),
-                style("Y").red(),
-                style("N").red(),
-                style("A").red(),
-                style("L").red()
+                "Unable to add {}, the two account has different commodities: {} - {}",
+                style(transaction).magenta(),
+                style(self.only_account).red(),
+                style(counter.0).red()
             ))?;
-            let answer = Answer::get(self.term)?;
-            match answer {
-                Answer::Yes => self.add_transaction(transaction)?,
-                Answer::No => self
-                    .term
-                    .write_line(&format!("Skipping {}", style(&transaction).magenta()))?,
-                Answer::Abort => return Ok(()),
-                Answer::All => {
-                    for current in idx..self.unmatched_transactions.len() {
-                        let transaction = &self.unmatched_transactions[idx];
-                        self.check_fee_configured(transaction)?;
-                        self.add_transaction(&self.unmatched_transactions[current])?;
-                    }
-                    return Ok(());
-                }
-            };
+            pending.push(transaction.clone());
+            return Ok(());
+        }
+        self.add_transaction(transaction, identity, counter)
+    }
+
+    fn check_counter_account(
+        &self,
+        transaction: &ExternalTransaction,
+        counter: &Account,
+    ) -> Result<()> {
+        if counter.commodity_guid != self.only_account.commodity_guid {
+            return Err(anyhow!(
+                "Unable to add {}, the two account has different commodities: {} - {}!",
+                transaction,
+                self.only_account,
+                counter
+            ));
         }
         Ok(())
     }
@@ -352,9 +1317,67 @@ impl AddTransactions<'_> {
         }
     }
 
-    fn add_transaction(&mut self, transaction: &ExternalTransaction) -> Result<()> {
-        self.term
-            .write_line(&format!("adding {}", style(&transaction).red()))?;
+    // The counter account from the first matching rule, or the specified one, with the
+    // description of the rule, if there is any
+    fn counter_account_for(
+        &self,
+        transaction: &ExternalTransaction,
+    ) -> Option<(&'a Account, Option<&'a String>)> {
+        match self.counter_rules.find(transaction) {
+            Some(rule) => Some((&rule.account, rule.description.as_ref())),
+            None => self.counter_account.map(|account| (account, None)),
+        }
+    }
+
+    // The account learned from the history, if no rule matches the transaction
+    fn suggestion_for(&self, transaction: &ExternalTransaction) -> Option<Suggestion<'a>> {
+        match self.counter_rules.find(transaction) {
+            Some(_) => None,
+            None => self.suggester.suggest(transaction),
+        }
+    }
+
+    fn report_no_counter_account(&self, transaction: &ExternalTransaction) -> Result<()> {
+        self.term.write_line(&format!(
+            "No rule matches {}, no counter account is specified, and no suggestion is accepted",
+            style(&transaction).magenta()
+        ))?;
+        Ok(())
+    }
+
+    // The accounts, memos and amounts of the splits of the new transaction
+    fn planned_splits(
+        &self,
+        transaction: &ExternalTransaction,
+        counter_account: &'a Account,
+        description: &str,
+    ) -> Result<Vec<(&'a Account, String, Decimal)>> {
+        let amount = transaction.get_amount().expect("Amount is expected!");
+        let fee_value = transaction.transaction_fee.unwrap_or_default();
+        let mut splits = vec![
+            (self.only_account, description.to_owned(), amount),
+            (
+                counter_account,
+                transaction.get_other_account_desc(),
+                -amount - fee_value,
+            ),
+        ];
+        if !fee_value.is_zero() {
+            let fee_account = self
+                .fee_account
+                .as_ref()
+                .context("Fee account is expected!")?;
+            splits.push((fee_account, description.to_owned(), fee_value));
+        }
+        Ok(splits)
+    }
+
+    // The new transaction of the row, with its splits
+    fn plan(
+        &mut self,
+        transaction: &ExternalTransaction,
+        (counter_account, rule_description): (&'a Account, Option<&String>),
+    ) -> Result<PlannedTransaction<'a>> {
         let commodity_guid = &self
             .only_account
             .commodity_guid
@@ -362,60 +1385,107 @@ impl AddTransactions<'_> {
             .expect("Commodity guid is not null");
         let commodity = CommoditiesQuery::get_by_guid(self.connection, commodity_guid)
             .expect("Currency not found!");
-        let tr_guid = format_guid(&GUID::rand().to_string());
-        let spend_date = transaction
-            .get_matching_date(Matching::BySpending)
-            .map(|d| d.and_hms_opt(12, 0, 0).expect("Correct date"));
-        let current_time = Local::now().naive_local();
-        let description = transaction
-            .get_description_or_category()
+        let description = rule_description
+            .cloned()
+            .or_else(|| transaction.get_description_or_category())
             .unwrap_or_else(|| "".to_owned());
-        let amount = transaction.get_amount().expect("Amount is expected!");
+        let splits = self.planned_splits(transaction, counter_account, &description)?;
+        Ok(PlannedTransaction {
+            guid: format_guid(&GUID::rand().to_string()),
+            commodity,
+            spend_date: transaction
+                .get_matching_date(Matching::BySpending)
+                .map(|d| d.and_hms_opt(12, 0, 0).expect("Correct date")),
+            entered: Local::now().naive_local(),
+            description,
+            splits,
+        })
+    }
 
-        let fee_value = &transaction.transaction_fee.unwrap_or_default();
+    fn preview(&self, planned: &PlannedTransaction) -> Result<()> {
+        self.term.write_line(&format!(
+            "  {}",
+            NewTransaction::preview(
+                &planned.guid,
+                &planned.commodity.guid,
+                planned.spend_date,
+                planned.entered,
+                &planned.description
+            )
+        ))?;
+        for (account, memo, amount) in &planned.splits {
+            self.term.write_line(&format!(
+                "    {} ({})",
+                NewSplit::preview(&planned.guid, account, memo, &planned.commodity, *amount),
+                account.name
+            ))?;
+        }
+        Ok(())
+    }
 
+    fn add_transaction(
+        &mut self,
+        transaction: &ExternalTransaction,
+        identity: &str,
+        counter: (&'a Account, Option<&String>),
+    ) -> Result<()> {
+        self.term
+            .write_line(&format!("adding {}", style(&transaction).red()))?;
+        let planned = self.plan(transaction, counter)?;
+        let Changes::Write(journal) = self.changes else {
+            return self.preview(&planned);
+        };
+        journal.begin(self.connection)?;
+        // the transaction and its splits are saved together, or not at all
+        self.connection
+            .transaction::<_, anyhow::Error, _>(|connection| {
+                planned.insert(connection, journal, &self.only_account.guid, identity)
+            })
+            .with_context(|| format!("Unable to add {}, nothing is saved", transaction))?;
+        Ok(())
+    }
+}
+
+// A new transaction of a statement row, printed on a dry run, or written
+struct PlannedTransaction<'a> {
+    guid: String,
+    commodity: Commodities,
+    spend_date: Option<NaiveDateTime>,
+    entered: NaiveDateTime,
+    description: String,
+    splits: Vec<(&'a Account, String, Decimal)>,
+}
+
+impl PlannedTransaction<'_> {
+    // Saves the transaction with its splits, and remembers the row it is created from
+    fn insert(
+        &self,
+        connection: &mut SqliteConnection,
+        journal: &Journal,
+        account_guid: &str,
+        identity: &str,
+    ) -> Result<()> {
         NewTransaction::insert(
-            self.connection,
-            &tr_guid,
-            &commodity.guid,
-            spend_date,
-            current_time,
-            &description,
-        );
-        let _split_id_from = NewSplit::insert(
-            self.connection,
-            &tr_guid,
-            self.only_account,
-            &description,
-            &commodity,
-            amount,
-        );
-        let _split_id_counter = NewSplit::insert(
-            self.connection,
-            &tr_guid,
-            self.counter_account,
-            &transaction.get_other_account_desc(),
-            &commodity,
-            -amount - fee_value,
-        );
-        if !(*fee_value).is_zero() {
-            let _fee_id_counter = NewSplit::insert(
-                self.connection,
-                &tr_guid,
-                self.fee_account.as_ref().expect("Fee account is expected!"),
-                &description,
-                &commodity,
-                *fee_value,
-            );
+            connection,
+            &self.guid,
+            &self.commodity.guid,
+            self.spend_date,
+            self.entered,
+            &self.description,
+        )?;
+        journal.created_transaction(connection, &self.guid)?;
+        for (account, memo, amount) in &self.splits {
+            let split_id = NewSplit::insert(
+                connection,
+                &self.guid,
+                account,
+                memo,
+                &self.commodity,
+                *amount,
+            )?;
+            journal.created_split(connection, &split_id)?;
         }
-        /*        self.term.write_line(&format!(
-            "trans id:{} \n\t{} - {} \n\t{} - {}",
-            tr_guid,
-            self.only_account.name,
-            split_id_from,
-            self.counter_account.name,
-            split_id_counter
-        ))?;*/
+        record_import(connection, account_guid, identity, &self.guid)?;
         Ok(())
     }
 }
@@ -435,8 +1505,255 @@ impl Answer {
                 Key::Char('A') => return Ok(Answer::Abort),
                 Key::Char('l') => return Ok(Answer::All),
                 Key::Char('L') => return Ok(Answer::All),
+                Key::Char('s') => return Ok(Answer::Suggested),
+                Key::Char('S') => return Ok(Answer::Suggested),
                 _ => {}
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{account, book, booked, external};
+    use crate::utils::table_exists;
+    use diesel::sql_query;
+
+    fn reviewed<'a>(
+        statement: &[ExternalTransaction],
+        row: usize,
+        counter: &'a Account,
+        add: bool,
+    ) -> ReviewedRow<'a> {
+        ReviewedRow {
+            row,
+            counter: Some(counter),
+            description: statement[row].description.clone().unwrap(),
+            add,
+        }
+    }
+
+    // The correlator of the statement rows and the booked transactions, without a book
+    fn new_correlator(
+        statement: Vec<ExternalTransaction>,
+        booked: Vec<(Split, Transaction)>,
+        tolerance: AmountTolerance,
+    ) -> TransactionCorrelator {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        let history = ImportHistory::load(&mut connection, "bank").unwrap();
+        let identities = identities(&statement);
+        let mut correlator = TransactionCorrelator::new(
+            ExternalTransactionList::new(statement, Matching::BySpending),
+            "bank".to_owned(),
+            Matching::BySpending,
+            DateWindow::default(),
+            tolerance,
+            identities,
+            history,
+            false,
+        );
+        for row in booked {
+            let date = row.1.posting().unwrap().date();
+            let list = correlator.transaction_map.entry(date).or_default();
+            list.push(TransactionPairing::new(row));
+        }
+        correlator
+    }
+
+    #[test]
+    fn test_split_payment() {
+        let statement = vec![
+            external(10, -700, "Spar 1"),
+            external(10, -500, "Spar 2"),
+            // with the refund, they add up to the payment too
+            external(10, -1400, "Spar 3"),
+            external(10, 200, "Spar refund"),
+        ];
+        let mut split = new_correlator(
+            statement,
+            vec![booked("tx1", 11, -1200, "Spar")],
+            AmountTolerance::default(),
+        );
+        assert_eq!(split.match_transactions(), vec![2, 3]);
+        let groups = split.get_groups();
+        assert_eq!(groups.len(), 1);
+        let amounts: Vec<Option<Decimal>> = groups[0]
+            .externals
+            .iter()
+            .map(|external| external.amount)
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![Some(Decimal::from(-700)), Some(Decimal::from(-500))]
+        );
+        assert!(!groups[0].pairings[0].is_ambiguous());
+
+        // either of the -700 rows can be the part of the payment
+        let statement = vec![
+            external(10, -700, "Spar 1"),
+            external(10, -500, "Spar 2"),
+            external(10, -700, "Spar 3"),
+        ];
+        let mut split = new_correlator(
+            statement,
+            vec![booked("tx1", 11, -1200, "Spar")],
+            AmountTolerance::default(),
+        );
+        assert_eq!(split.match_transactions(), vec![2]);
+        assert!(split.get_groups()[0].pairings[0].is_ambiguous());
+    }
+
+    #[test]
+    fn test_exact_amount_wins() {
+        let tolerance = AmountTolerance {
+            absolute: Decimal::from(10),
+            percent: Decimal::ZERO,
+        };
+        let mut correlator = new_correlator(
+            vec![external(10, -1000, "Tesco")],
+            vec![
+                booked("tx1", 10, -995, "Tesco"),
+                booked("tx2", 10, -1000, "Tesco"),
+            ],
+            tolerance,
+        );
+        assert!(correlator.match_transactions().is_empty());
+        let unmatched: Vec<&str> = correlator
+            .get_unmatched()
+            .iter()
+            .map(|pairing| pairing.transaction().guid.as_str())
+            .collect();
+        assert_eq!(unmatched, vec!["tx1"]);
+
+        // the inexact one is paired, when there is no exact one
+        let mut correlator = new_correlator(
+            vec![external(10, -1000, "Tesco")],
+            vec![booked("tx1", 10, -995, "Tesco")],
+            tolerance,
+        );
+        assert!(correlator.match_transactions().is_empty());
+    }
+
+    #[test]
+    fn test_report_rows_imported_earlier() {
+        let statement = vec![external(10, -1000, "Tesco"), external(12, -300, "Lidl")];
+        // Lidl was imported earlier, but its transaction is moved to an other account
+        let mut connection = book(&[]);
+        sql_query("INSERT INTO transactions VALUES ('moved', 'huf', '', NULL, NULL, 'Lidl')")
+            .execute(&mut connection)
+            .unwrap();
+        record_import(&mut connection, "bank", &identities(&statement)[1], "moved").unwrap();
+        let mut correlator = new_correlator(
+            statement,
+            vec![booked("tx1", 10, -1000, "Tesco")],
+            AmountTolerance::default(),
+        );
+        correlator.history = ImportHistory::load(&mut connection, "bank").unwrap();
+        assert!(correlator.match_transactions().is_empty());
+        let report = correlator.build_report(&account("bank", "bank", "huf"), &[]);
+        assert_eq!(report.matched.len(), 1);
+        let imported_earlier: Vec<&str> = report
+            .imported_earlier
+            .iter()
+            .map(|row| row.description.as_str())
+            .collect();
+        assert_eq!(imported_earlier, vec!["Lidl"]);
+    }
+
+    // The number of transactions and splits in the book
+    fn counts(connection: &mut SqliteConnection) -> (i64, i64) {
+        use crate::schema::{splits, transactions};
+        (
+            transactions::table.count().get_result(connection).unwrap(),
+            splits::table.count().get_result(connection).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_commit_review() {
+        let mut connection = book(&[]);
+        let statement = vec![external(5, -500, "Tesco"), external(6, -300, "Lidl")];
+        let identities = vec!["hash:tesco".to_owned(), "hash:lidl".to_owned()];
+        let matched = vec![("hash:spar".to_owned(), "tx0".to_owned())];
+        let (bank, food, euro) = (
+            account("bank", "bank", "huf"),
+            account("food", "food", "huf"),
+            account("euro", "euro", "eur"),
+        );
+        let term = Term::stderr();
+        let journal = Journal::new("test", &term);
+        let (rules, suggester, acceptance) = (
+            CounterAccountRules::default(),
+            AccountSuggester::default(),
+            Acceptance::default(),
+        );
+        let mut add_transactions = AddTransactions {
+            connection: &mut connection,
+            unmatched_transactions: &statement,
+            identities: &identities,
+            only_account: &bank,
+            counter_account: None,
+            counter_rules: &rules,
+            suggester: &suggester,
+            fee_account: &None,
+            acceptance: &acceptance,
+            changes: Changes::Write(&journal),
+            term: &term,
+        };
+
+        // the second row is in an other commodity, nothing is written
+        let rows = [
+            reviewed(&statement, 0, &food, true),
+            reviewed(&statement, 1, &euro, true),
+        ];
+        assert!(add_transactions.commit_review(&rows, &matched).is_err());
+        assert_eq!(counts(add_transactions.connection), (0, 0));
+        assert!(!table_exists(add_transactions.connection, "financ_imports").unwrap());
+
+        let rows = [
+            reviewed(&statement, 0, &food, true),
+            reviewed(&statement, 1, &euro, false),
+        ];
+        let pending = add_transactions.commit_review(&rows, &matched).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(counts(&mut connection), (1, 2));
+        let history = ImportHistory::load(&mut connection, "bank").unwrap();
+        assert!(history.transaction_of("hash:tesco").is_some());
+    }
+
+    #[test]
+    fn test_counter_account_in_other_commodity() {
+        let mut connection = book(&[]);
+        let statement = vec![external(5, -500, "Tesco")];
+        let identities = vec!["hash:tesco".to_owned()];
+        let (bank, euro) = (
+            account("bank", "bank", "huf"),
+            account("euro", "euro", "eur"),
+        );
+        let term = Term::stderr();
+        let journal = Journal::new("test", &term);
+        let (rules, suggester) = (CounterAccountRules::default(), AccountSuggester::default());
+        let acceptance = Acceptance {
+            accept_all: true,
+            ..Default::default()
+        };
+        let mut add_transactions = AddTransactions {
+            connection: &mut connection,
+            unmatched_transactions: &statement,
+            identities: &identities,
+            only_account: &bank,
+            counter_account: Some(&euro),
+            counter_rules: &rules,
+            suggester: &suggester,
+            fee_account: &None,
+            acceptance: &acceptance,
+            changes: Changes::Write(&journal),
+            term: &term,
+        };
+        // the row is left pending, instead of transferring between the commodities
+        let pending = add_transactions.try_to_fix().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(counts(&mut connection), (0, 0));
+    }
+}
@@ -0,0 +1,181 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use diesel::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::external_models::ExternalTransaction;
+use crate::models::Account;
+use crate::query::accounts::{accounts_with_full_names, find_by_path_or_guid};
+
+// A rules file picks the counter account of the new transactions, like
+//
+// [[rule]]
+// other_account_name = "(?i)^tesco"
+// account = "Expenses:Groceries"
+// set_description = "Groceries"
+//
+// Every given pattern has to match, the first matching rule is used.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    description: Option<String>,
+    other_account: Option<String>,
+    other_account_name: Option<String>,
+    category: Option<String>,
+    // full path, like Expenses:Groceries, or GUID of the account
+    account: String,
+    // the description of the new transaction, instead of the one from the statement
+    set_description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Description,
+    OtherAccount,
+    OtherAccountName,
+    Category,
+}
+
+impl Field {
+    fn value(self, transaction: &ExternalTransaction) -> Option<&String> {
+        match self {
+            Field::Description => transaction.description.as_ref(),
+            Field::OtherAccount => transaction.other_account.as_ref(),
+            Field::OtherAccountName => transaction.other_account_name.as_ref(),
+            Field::Category => transaction.category.as_ref(),
+        }
+    }
+}
+
+pub struct CounterAccountRule {
+    patterns: Vec<(Field, Regex)>,
+    pub account: Account,
+    pub description: Option<String>,
+}
+
+impl CounterAccountRule {
+    fn matches(&self, transaction: &ExternalTransaction) -> bool {
+        self.patterns.iter().all(|(field, pattern)| {
+            field
+                .value(transaction)
+                .is_some_and(|value| pattern.is_match(value))
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct CounterAccountRules {
+    pub rules: Vec<CounterAccountRule>,
+}
+
+impl CounterAccountRules {
+    pub fn load(connection: &mut SqliteConnection, path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read rules file {}", path.display()))?;
+        let file: RulesFile = toml::from_str(&content)
+            .with_context(|| format!("Invalid rules file {}", path.display()))?;
+        let accounts = accounts_with_full_names(connection);
+        let mut rules = Vec::new();
+        for definition in file.rule {
+            let patterns = [
+                (Field::Description, &definition.description),
+                (Field::OtherAccount, &definition.other_account),
+                (Field::OtherAccountName, &definition.other_account_name),
+                (Field::Category, &definition.category),
+            ]
+            .into_iter()
+            .filter_map(|(field, pattern)| pattern.as_ref().map(|p| (field, p)))
+            .map(|(field, pattern)| Ok((field, Regex::new(pattern)?)))
+            .collect::<Result<Vec<_>>>()?;
+            if patterns.is_empty() {
+                return Err(anyhow!(
+                    "The rule for {} has no pattern to match!",
+                    definition.account
+                ));
+            }
+            let account = find_by_path_or_guid(&accounts, &definition.account)
+                .cloned()
+                .with_context(|| format!("Account not found:'{}'!", definition.account))?;
+            rules.push(CounterAccountRule {
+                patterns,
+                account,
+                description: definition.set_description,
+            });
+        }
+        Ok(CounterAccountRules { rules })
+    }
+
+    pub fn find(&self, transaction: &ExternalTransaction) -> Option<&CounterAccountRule> {
+        self.rules.iter().find(|rule| rule.matches(transaction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::sql_query;
+    use std::io::Write;
+
+    fn transaction(description: &str, other_account_name: &str) -> ExternalTransaction {
+        ExternalTransaction {
+            date: None,
+            booking_date: None,
+            amount: None,
+            category: None,
+            description: Some(description.to_owned()),
+            other_account: None,
+            other_account_name: Some(other_account_name.to_owned()),
+            textual_date: None,
+            transaction_fee: None,
+            reference: None,
+        }
+    }
+
+    #[test]
+    fn test_rules_pick_account_by_full_name() {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        for statement in [
+            "CREATE TABLE accounts(guid text(32) PRIMARY KEY NOT NULL, name text NOT NULL, \
+             account_type text NOT NULL, commodity_guid text(32), commodity_scu integer NOT NULL, \
+             non_std_scu integer NOT NULL, parent_guid text(32), code text, description text, \
+             hidden integer, placeholder integer)",
+            "INSERT INTO accounts VALUES ('root', 'Root Account', 'ROOT', NULL, 0, 0, NULL, \
+             NULL, NULL, 0, 0), ('exp', 'Expenses', 'EXPENSE', 'huf', 100, 0, 'root', NULL, \
+             NULL, 0, 0), ('food', 'Groceries', 'EXPENSE', 'huf', 100, 0, 'exp', NULL, NULL, \
+             0, 0)",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("financ-rules-{}.toml", std::process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        writeln!(
+            file,
+            "[[rule]]\nother_account_name = \"(?i)^tesco\"\ndescription = \"CARD\"\n\
+             account = \"Expenses:Groceries\"\nset_description = \"Groceries\"\n\n\
+             [[rule]]\ndescription = \"Rent\"\naccount = \"exp\""
+        )
+        .unwrap();
+        let rules = CounterAccountRules::load(&mut connection, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let groceries = rules
+            .find(&transaction("CARD 1234", "TESCO Budapest"))
+            .unwrap();
+        assert_eq!(groceries.account.guid, "food");
+        assert_eq!(groceries.description.as_deref(), Some("Groceries"));
+        // every pattern of the rule has to match
+        assert!(rules.find(&transaction("Transfer", "Tesco")).is_none());
+        let rent = rules.find(&transaction("Rent March", "Landlord")).unwrap();
+        assert_eq!(rent.account.name, "Expenses");
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use guid_create::GUID;
@@ -74,6 +77,18 @@ impl<'a> NewSplit<'a> {
         NewSplit::new_with_defaults(split_guid, tx_guid, &account.guid, memo, value, qty)
     }
 
+    // The split, which insert would save, with a new GUID
+    pub fn preview(
+        tx_guid: &'a str,
+        account: &'a Account,
+        memo: &'a str,
+        currency: &Commodities,
+        amount: Decimal,
+    ) -> String {
+        let split_guid = format_guid(&GUID::rand().to_string());
+        NewSplit::create_split(&split_guid, tx_guid, account, memo, currency, amount).to_string()
+    }
+
     pub fn insert(
         connection: &mut SqliteConnection,
         tx_guid: &'a str,
@@ -81,7 +96,7 @@ impl<'a> NewSplit<'a> {
         memo: &'a str,
         currency: &Commodities,
         amount: Decimal,
-    ) -> String {
+    ) -> Result<String> {
         let split_guid = format_guid(&GUID::rand().to_string());
         {
             let split =
@@ -90,10 +105,15 @@ impl<'a> NewSplit<'a> {
             let inserted_rows = diesel::insert_into(splits::table)
                 .values(&split)
                 .execute(connection)
-                .expect("Error saving new split");
-            assert_eq!(1, inserted_rows);
+                .context("Error saving new split")?;
+            ensure!(
+                inserted_rows == 1,
+                "Saving split {} inserted {} rows!",
+                split_guid,
+                inserted_rows
+            );
         }
-        split_guid
+        Ok(split_guid)
     }
 }
 
@@ -115,6 +135,28 @@ impl<'a> NewTransaction<'a> {
         }
     }
 
+    // The transaction, which insert would save
+    pub fn preview(
+        guid: &'a str,
+        currency_guid: &'a str,
+        post_date: Option<NaiveDateTime>,
+        enter_date: NaiveDateTime,
+        description: &'a str,
+    ) -> String {
+        let formatted_date = post_date
+            .map(|x| format_sqlite_date(&x))
+            .unwrap_or_default();
+        let formatted_enter_date = format_sqlite_date(&enter_date);
+        NewTransaction::new(
+            guid,
+            currency_guid,
+            &formatted_date,
+            &formatted_enter_date,
+            description,
+        )
+        .to_string()
+    }
+
     pub fn insert(
         connection: &mut SqliteConnection,
         guid: &'a str,
@@ -122,7 +164,7 @@ impl<'a> NewTransaction<'a> {
         post_date: Option<NaiveDateTime>,
         enter_date: NaiveDateTime,
         description: &'a str,
-    ) -> usize {
+    ) -> Result<usize> {
         let formatted_date = post_date
             .map(|x| format_sqlite_date(&x))
             .unwrap_or_default();
@@ -138,8 +180,40 @@ impl<'a> NewTransaction<'a> {
         let inserted_rows = diesel::insert_into(transactions::table)
             .values(transaction)
             .execute(connection)
-            .expect("Error saving transaction");
-        assert_eq!(1, inserted_rows);
-        inserted_rows
+            .context("Error saving transaction")?;
+        ensure!(
+            inserted_rows == 1,
+            "Saving transaction {} inserted {} rows!",
+            guid,
+            inserted_rows
+        );
+        Ok(inserted_rows)
+    }
+}
+
+impl fmt::Display for NewTransaction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "transaction {} currency={} post_date='{}' enter_date='{}' description='{}'",
+            self.guid, self.currency_guid, self.post_date, self.enter_date, self.description
+        )
+    }
+}
+
+impl fmt::Display for NewSplit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "split {} tx={} account={} memo='{}' value={}/{} quantity={}/{}",
+            self.guid,
+            self.tx_guid,
+            self.account_guid,
+            self.memo,
+            self.value_num,
+            self.value_denom,
+            self.quantity_num,
+            self.quantity_denom
+        )
     }
 }
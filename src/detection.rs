@@ -0,0 +1,190 @@
+use std::cmp::Reverse;
+use std::fs;
+
+use anyhow::{Context, Result};
+use console::{Term, style};
+
+use crate::cli::CsvArgs;
+use crate::external_models::{SheetDefinition, SheetParser, StatementFormat, StatementParser};
+use crate::format_file::{FormatFile, list_format_files};
+use crate::formats::{CsvExport, SheetFormat};
+use crate::readers::camt::CamtFormat;
+use crate::readers::csv::{CsvOptions, parse_csv, sniff_delimiter};
+use crate::readers::mt940::Mt940Format;
+use crate::readers::ofx::OfxFormat;
+use crate::readers::qif::QifFormat;
+
+// Candidates closer than this are too close to call
+const AMBIGUITY_MARGIN: u32 = 10;
+
+pub struct Candidate {
+    pub name: String,
+    pub score: u32,
+    pub format: StatementFormat,
+    pub csv_options: Option<CsvOptions>,
+}
+
+// Scores the input against the statement formats, the built-in sheet formats and the format files,
+// the best candidate is the first.
+pub fn detect_formats(
+    input_file: &str,
+    sheet_name: Option<String>,
+    csv: &CsvArgs,
+) -> Result<Vec<Candidate>> {
+    let content = fs::read(input_file).with_context(|| format!("Unable to read {}", input_file))?;
+    let statement_parsers: [(&str, Box<dyn StatementParser>); 4] = [
+        ("ofx", Box::new(OfxFormat)),
+        ("camt", Box::new(CamtFormat)),
+        ("mt940", Box::new(Mt940Format)),
+        ("qif", Box::new(QifFormat)),
+    ];
+    let mut result: Vec<Candidate> = statement_parsers
+        .into_iter()
+        .map(|(name, parser)| Candidate {
+            name: name.to_owned(),
+            score: parser.sniff(&content),
+            format: StatementFormat::Statement(parser),
+            csv_options: None,
+        })
+        .collect();
+
+    let is_csv = input_file.to_lowercase().ends_with(".csv");
+    let csv_options = if is_csv {
+        Some(CsvOptions::new(
+            csv.delimiter.or_else(|| sniff_delimiter(&content)),
+            csv.quote,
+            csv.skip_rows,
+            csv.encoding.as_deref(),
+        )?)
+    } else {
+        None
+    };
+    let range = match &csv_options {
+        Some(options) => Some(parse_csv(&content, options)?),
+        // not every input is a spreadsheet, those are only checked against the statement formats
+        None => SheetDefinition::new(input_file)
+            .ok()
+            .and_then(|mut definition| definition.range(sheet_name).1),
+    };
+    if let Some(range) = range {
+        for format in SheetFormat::ALL {
+            let parser: Box<dyn SheetParser> = match csv_options {
+                Some(_) => Box::new(CsvExport(format)),
+                None => Box::new(format),
+            };
+            result.push(Candidate {
+                name: format.name().to_owned(),
+                score: parser.sniff(&range),
+                format: StatementFormat::Sheet(parser),
+                csv_options: csv_options.clone(),
+            });
+        }
+        for (name, path) in list_format_files() {
+            let loaded = FormatFile::load(&path).and_then(|format_file| {
+                let mapping = format_file.to_mapping()?;
+                let file_csv_options = if is_csv {
+                    format_file.csv_options(
+                        csv.delimiter,
+                        csv.quote,
+                        csv.skip_rows,
+                        csv.encoding.as_deref(),
+                    )?
+                } else {
+                    None
+                };
+                Ok((mapping, file_csv_options))
+            });
+            let (mapping, file_csv_options) = match loaded {
+                Ok(loaded) => loaded,
+                Err(error) => {
+                    println!("Skipping format file {}: {:#}", path.display(), error);
+                    continue;
+                }
+            };
+            let score = match &file_csv_options {
+                Some(options) => mapping.sniff(&parse_csv(&content, options)?),
+                None => mapping.sniff(&range),
+            };
+            result.push(Candidate {
+                name,
+                score,
+                format: StatementFormat::Sheet(Box::new(mapping)),
+                csv_options: file_csv_options.or_else(|| csv_options.clone()),
+            });
+        }
+    }
+    result.sort_by_key(|candidate| Reverse(candidate.score));
+    Ok(result)
+}
+
+// Picks the best candidate, unless there is no good one, or the best ones are too close
+pub fn choose_format(
+    candidates: Vec<Candidate>,
+    term: &Term,
+) -> Result<(StatementFormat, Option<CsvOptions>)> {
+    let mut matching = candidates
+        .into_iter()
+        .filter(|candidate| candidate.score > 0);
+    let best = matching
+        .next()
+        .ok_or_else(|| anyhow!("Unable to detect the format of the input, use --format!"))?;
+    let contenders: Vec<Candidate> = matching
+        .filter(|candidate| candidate.score + AMBIGUITY_MARGIN > best.score)
+        .collect();
+    if !contenders.is_empty() {
+        term.write_line("The input matches multiple formats:")?;
+        for candidate in std::iter::once(&best).chain(contenders.iter()) {
+            term.write_line(&format!(
+                "  {:<16} {}%",
+                style(&candidate.name).blue(),
+                style(candidate.score).cyan()
+            ))?;
+        }
+        return Err(anyhow!("Ambiguous format, choose one with --format!"));
+    }
+    term.write_line(&format!(
+        "Detected format '{}' ({}%)",
+        style(&best.name).blue(),
+        style(best.score).cyan()
+    ))?;
+    Ok((best.format, best.csv_options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(format: SheetFormat, score: u32) -> Candidate {
+        Candidate {
+            name: format.name().to_owned(),
+            score,
+            format: StatementFormat::Sheet(Box::new(format)),
+            csv_options: None,
+        }
+    }
+
+    #[test]
+    fn test_choose_format() {
+        let term = Term::stderr();
+        let (format, _) = choose_format(
+            vec![
+                candidate(SheetFormat::Magnet, 100),
+                candidate(SheetFormat::Granit, 40),
+                candidate(SheetFormat::Otp, 0),
+            ],
+            &term,
+        )
+        .unwrap();
+        assert!(matches!(format, StatementFormat::Sheet(_)));
+
+        let ambiguous = choose_format(
+            vec![
+                candidate(SheetFormat::Otp, 95),
+                candidate(SheetFormat::Otp2020, 90),
+            ],
+            &term,
+        );
+        assert!(ambiguous.is_err());
+        assert!(choose_format(vec![candidate(SheetFormat::Otp, 0)], &term).is_err());
+    }
+}
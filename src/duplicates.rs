@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::Result;
+use console::{Key, Term, style};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::journal::{Changes, Journal};
+use crate::lock::with_book_lock;
+use crate::models::{Account, Split, Transaction};
+use crate::query::transactions::TransactionQuery;
+use crate::similarity::similarity;
+
+pub struct DuplicatesCommand {
+    // the whole book is scanned without an account
+    pub account: Option<Account>,
+    pub max_days: u32,
+    pub min_similarity: f64,
+    pub auto: bool,
+    pub force: bool,
+    pub dry_run: bool,
+}
+
+// The transactions, which are probably the same, with one of their splits, by the date
+pub struct DuplicateGroup {
+    pub transactions: Vec<(Split, Transaction)>,
+}
+
+// Groups the splits of the same account with the same amount, where every transaction is within
+// max_days of the others, and their descriptions are similar to each other. A transfer is found
+// in both of its accounts, but listed only once.
+pub fn find_duplicates(
+    rows: Vec<(Split, Transaction)>,
+    max_days: u32,
+    min_similarity: f64,
+) -> Vec<DuplicateGroup> {
+    let mut by_amount: BTreeMap<(&str, Decimal), Vec<usize>> = BTreeMap::new();
+    for (idx, (split, _)) in rows.iter().enumerate() {
+        by_amount
+            .entry((split.account_guid.as_str(), split.get_quantity_as_decimal()))
+            .or_default()
+            .push(idx);
+    }
+    let date_of = |idx: usize| rows[idx].1.posting().map(|posted| posted.date());
+    let is_duplicate = |first: usize, second: usize| {
+        let (first_tx, second_tx) = (&rows[first].1, &rows[second].1);
+        let close = match (date_of(first), date_of(second)) {
+            (Some(first_date), Some(second_date)) => {
+                (second_date - first_date).num_days().abs() <= i64::from(max_days)
+            }
+            _ => false,
+        };
+        close
+            && first_tx.guid != second_tx.guid
+            && similarity(
+                first_tx.description.as_deref().unwrap_or_default(),
+                second_tx.description.as_deref().unwrap_or_default(),
+            ) >= min_similarity
+    };
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for indexes in by_amount.values_mut() {
+        indexes.sort_by_key(|idx| date_of(*idx));
+        let mut grouped = vec![false; indexes.len()];
+        for position in 0..indexes.len() {
+            if grouped[position] {
+                continue;
+            }
+            let mut members = vec![indexes[position]];
+            for other in position + 1..indexes.len() {
+                if !grouped[other]
+                    && members
+                        .iter()
+                        .all(|member| is_duplicate(*member, indexes[other]))
+                {
+                    grouped[other] = true;
+                    members.push(indexes[other]);
+                }
+            }
+            if members.len() > 1 {
+                groups.push(members);
+            }
+        }
+    }
+    let mut seen: HashSet<BTreeSet<String>> = HashSet::new();
+    groups.retain(|members| {
+        seen.insert(
+            members
+                .iter()
+                .map(|idx| rows[*idx].1.guid.clone())
+                .collect(),
+        )
+    });
+    groups.sort_by_key(|members| date_of(members[0]));
+    let mut rows: Vec<Option<(Split, Transaction)>> = rows.into_iter().map(Some).collect();
+    groups
+        .into_iter()
+        .map(|members| DuplicateGroup {
+            transactions: members
+                .into_iter()
+                .filter_map(|idx| rows[idx].take())
+                .collect(),
+        })
+        .collect()
+}
+
+fn load_splits(connection: &mut SqliteConnection, tx_guid: &str) -> Result<Vec<Split>> {
+    use crate::schema::splits;
+    Ok(splits::table
+        .filter(splits::tx_guid.eq(tx_guid))
+        .select(Split::as_select())
+        .order(splits::guid)
+        .load(connection)?)
+}
+
+fn text_length(text: &str) -> usize {
+    text.trim().chars().count()
+}
+
+fn is_reconciled(split: &Split) -> bool {
+    split.reconcile_state == "c" || split.reconcile_state == "y"
+}
+
+// The transaction kept by a merge: the reconciled one, or the one with more splits,
+// or the one with the longer texts
+fn richness(transaction: &Transaction, splits: &[Split]) -> (usize, usize, usize) {
+    let reconciled = splits.iter().filter(|split| is_reconciled(split)).count();
+    let texts = text_length(transaction.description.as_deref().unwrap_or_default())
+        + splits
+            .iter()
+            .map(|split| text_length(&split.memo))
+            .sum::<usize>();
+    (reconciled, splits.len(), texts)
+}
+
+// The transactions of the group with all of their splits, the kept one is the first
+fn load_members<'a>(
+    connection: &mut SqliteConnection,
+    group: &'a DuplicateGroup,
+) -> Result<Vec<(&'a Transaction, Vec<Split>)>> {
+    let mut members = Vec::new();
+    for (_, transaction) in &group.transactions {
+        members.push((transaction, load_splits(connection, &transaction.guid)?));
+    }
+    members.sort_by_key(|(transaction, splits)| std::cmp::Reverse(richness(transaction, splits)));
+    Ok(members)
+}
+
+// Deletes the other transactions of the group, the kept one gets their longer description,
+// and the longer memos of their splits with the same account and amount
+fn merge(
+    connection: &mut SqliteConnection,
+    members: &[(&Transaction, Vec<Split>)],
+    changes: Changes,
+    term: &Term,
+) -> Result<()> {
+    let (kept, kept_splits) = &members[0];
+    let mut description = kept.description.clone().unwrap_or_default();
+    let mut memos: Vec<String> = kept_splits.iter().map(|split| split.memo.clone()).collect();
+    for (other, other_splits) in &members[1..] {
+        let other_description = other.description.as_deref().unwrap_or_default();
+        if text_length(other_description) > text_length(&description) {
+            description = other_description.to_owned();
+        }
+        for (split, memo) in kept_splits.iter().zip(memos.iter_mut()) {
+            let same = other_splits.iter().find(|other_split| {
+                other_split.account_guid == split.account_guid
+                    && other_split.get_quantity_as_decimal() == split.get_quantity_as_decimal()
+            });
+            if let Some(other_split) = same
+                && text_length(&other_split.memo) > text_length(memo)
+            {
+                *memo = other_split.memo.clone();
+            }
+        }
+    }
+    let deleted = members.len() - 1;
+    let Changes::Write(journal) = changes else {
+        term.write_line(&format!(
+            "  would keep {} as '{}', and delete {} transactions",
+            kept.guid,
+            description,
+            style(deleted).cyan()
+        ))?;
+        return Ok(());
+    };
+    journal.begin(connection)?;
+    connection.transaction::<_, anyhow::Error, _>(|connection| {
+        use crate::schema::{splits, transactions};
+        let previous = kept.description.clone().unwrap_or_default();
+        if description != previous {
+            diesel::update(transactions::table.find(&kept.guid))
+                .set(transactions::description.eq(&description))
+                .execute(connection)?;
+            journal.changed_description(connection, &kept.guid, &previous)?;
+        }
+        for (split, memo) in kept_splits.iter().zip(&memos) {
+            if *memo != split.memo {
+                diesel::update(splits::table.find(&split.guid))
+                    .set(splits::memo.eq(memo))
+                    .execute(connection)?;
+                journal.changed_memo(connection, &split.guid, &split.memo)?;
+            }
+        }
+        for (other, other_splits) in &members[1..] {
+            for split in other_splits {
+                journal.deleted_split(connection, &split.guid)?;
+                diesel::delete(splits::table.find(&split.guid)).execute(connection)?;
+            }
+            journal.deleted_transaction(connection, &other.guid)?;
+            diesel::delete(transactions::table.find(&other.guid)).execute(connection)?;
+        }
+        Ok(())
+    })?;
+    term.write_line(&format!(
+        "  kept {}, deleted {} transactions",
+        style(&kept.guid).green(),
+        style(deleted).cyan()
+    ))?;
+    Ok(())
+}
+
+enum Answer {
+    Yes,
+    No,
+    Abort,
+}
+
+impl Answer {
+    fn get(term: &Term) -> Result<Answer> {
+        loop {
+            match term.read_key()? {
+                Key::Char('y') | Key::Char('Y') | Key::Enter => return Ok(Answer::Yes),
+                Key::Char('n') | Key::Char('N') => return Ok(Answer::No),
+                Key::Char('a') | Key::Char('A') | Key::Escape => return Ok(Answer::Abort),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl DuplicatesCommand {
+    fn merge_groups(
+        &self,
+        connection: &mut SqliteConnection,
+        groups: &[DuplicateGroup],
+        changes: Changes,
+        term: &Term,
+    ) -> Result<usize> {
+        let mut merged = 0;
+        for group in groups {
+            for (split, transaction) in &group.transactions {
+                term.write_line(&format!(" - {} {}", transaction, split))?;
+            }
+            let members = load_members(connection, group)?;
+            let reconciled = members
+                .iter()
+                .filter(|(_, splits)| splits.iter().any(is_reconciled))
+                .count();
+            // merging would delete a reconciled transaction
+            if reconciled > 1 {
+                term.write_line(&format!(
+                    "  skipped, {} of them are reconciled",
+                    style(reconciled).red()
+                ))?;
+                continue;
+            }
+            if !changes.is_dry_run() && !self.auto {
+                term.write_line(&format!(
+                    "Merge them? [{}es/{}o/{}bort]",
+                    style("Y").red(),
+                    style("N").red(),
+                    style("A").red()
+                ))?;
+                match Answer::get(term)? {
+                    Answer::Yes => {}
+                    Answer::No => continue,
+                    Answer::Abort => break,
+                }
+            }
+            merge(connection, &members, changes, term)?;
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    pub fn execute(&self, connection: &mut SqliteConnection, term: &Term) -> Result<usize> {
+        let query = TransactionQuery {
+            limit: i64::MAX,
+            txid_filter: None,
+            account_filter: self.account.as_ref().map(|account| account.guid.clone()),
+            description_filter: None,
+            memo_filter: None,
+            before_filter: None,
+            after_filter: None,
+        };
+        let groups = find_duplicates(
+            query.execute(connection),
+            self.max_days,
+            self.min_similarity,
+        );
+        term.write_line(&format!(
+            "Found {} groups of duplicated transactions in {}",
+            style(groups.len()).cyan(),
+            match &self.account {
+                Some(account) => style(account.name.clone()).blue(),
+                None => style("the book".to_owned()).blue(),
+            }
+        ))?;
+        if groups.is_empty() {
+            return Ok(0);
+        }
+        if self.dry_run {
+            return self.merge_groups(connection, &groups, Changes::DryRun, term);
+        }
+        with_book_lock(connection, self.force, term, |connection| {
+            let journal = Journal::new("duplicates", term);
+            self.merge_groups(connection, &groups, Changes::Write(&journal), term)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::undo;
+    use crate::test_fixtures::{book, booked_on};
+    use diesel::sql_query;
+
+    fn guids(groups: &[DuplicateGroup]) -> Vec<Vec<&str>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .transactions
+                    .iter()
+                    .map(|(_, tx)| tx.guid.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_and_merge_duplicates() {
+        let mut rows = vec![
+            booked_on("t1", "2024-01-05", -500, "Tesco"),
+            booked_on("t2", "2024-01-06", -500, "CARD TESCO BUDAPEST"),
+            // too late
+            booked_on("t3", "2024-01-15", -500, "Tesco"),
+            // an other amount, or description
+            booked_on("t4", "2024-01-05", -600, "Tesco"),
+            booked_on("t5", "2024-01-05", -500, "Rent"),
+            // a chain, where the last one is too late for the first
+            booked_on("t6", "2024-02-01", -700, "Spar"),
+            booked_on("t7", "2024-02-03", -700, "Spar"),
+            booked_on("t8", "2024-02-05", -700, "Spar"),
+        ];
+        let (mut split, transaction) = booked_on("t9", "2024-03-01", 0, "Posta");
+        (split.quantity_num, split.quantity_denom) = (-12000, 10);
+        rows.push((split, transaction));
+        rows.push(booked_on("t10", "2024-03-01", -1200, "Posta"));
+        let groups = find_duplicates(rows, 3, 0.6);
+        assert_eq!(
+            guids(&groups),
+            vec![vec!["t1", "t2"], vec!["t6", "t7"], vec!["t9", "t10"]]
+        );
+
+        let mut connection = book(&[
+            "INSERT INTO transactions VALUES \
+             ('t1', 'huf', '', '2024-01-05 10:00:00', NULL, 'Tesco'), \
+             ('t2', 'huf', '', '2024-01-06 10:00:00', NULL, 'CARD TESCO BUDAPEST')",
+            "INSERT INTO splits VALUES \
+             ('s1', 't1', 'bank', '', '', 'c', NULL, -500, 1, -500, 1, NULL), \
+             ('s1f', 't1', 'food', '', '', 'n', NULL, 500, 1, 500, 1, NULL), \
+             ('s2', 't2', 'bank', 'card 1234', '', 'n', NULL, -500, 1, -500, 1, NULL), \
+             ('s2f', 't2', 'food', '', '', 'n', NULL, 500, 1, 500, 1, NULL)",
+        ]);
+        let term = Term::stderr();
+        let rows = TransactionQuery {
+            limit: i64::MAX,
+            txid_filter: None,
+            account_filter: None,
+            description_filter: None,
+            memo_filter: None,
+            before_filter: None,
+            after_filter: None,
+        }
+        .execute(&mut connection);
+        // the transfer is found in both accounts, but listed once
+        let groups = find_duplicates(rows, 3, 0.6);
+        assert_eq!(groups.len(), 1);
+
+        let journal = Journal::new("test", &term);
+        let members = load_members(&mut connection, &groups[0]).unwrap();
+        merge(&mut connection, &members, Changes::Write(&journal), &term).unwrap();
+        // the reconciled one is kept, with the richer texts of the other
+        let kept = load_splits(&mut connection, "t1").unwrap();
+        assert_eq!(kept[0].memo, "card 1234");
+        assert!(load_splits(&mut connection, "t2").unwrap().is_empty());
+        let descriptions: Vec<Option<String>> = {
+            use crate::schema::transactions;
+            transactions::table
+                .select(transactions::description)
+                .order(transactions::guid)
+                .load(&mut connection)
+                .unwrap()
+        };
+        assert_eq!(descriptions, vec![Some("CARD TESCO BUDAPEST".to_owned())]);
+
+        undo(&mut connection, None, false, &term).unwrap();
+        assert_eq!(load_splits(&mut connection, "t1").unwrap()[0].memo, "");
+        assert_eq!(load_splits(&mut connection, "t2").unwrap().len(), 2);
+
+        // both of them are reconciled, neither is deleted
+        sql_query("UPDATE splits SET reconcile_state = 'y' WHERE guid = 's2f'")
+            .execute(&mut connection)
+            .unwrap();
+        let command = DuplicatesCommand {
+            account: None,
+            max_days: 3,
+            min_similarity: 0.6,
+            auto: true,
+            force: false,
+            dry_run: false,
+        };
+        let merged = command
+            .merge_groups(&mut connection, &groups, Changes::Write(&journal), &term)
+            .unwrap();
+        assert_eq!(merged, 0);
+        assert_eq!(load_splits(&mut connection, "t2").unwrap().len(), 2);
+    }
+}
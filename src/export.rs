@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use console::{Term, style};
+use diesel::prelude::*;
+
+use crate::models::{Account, Split, Transaction};
+use crate::query::accounts::AccountQuery;
+use crate::query::transactions::{TransactionQuery, load_counter_splits};
+
+pub struct ExportCommand {
+    pub account_query: AccountQuery,
+    pub before: Option<NaiveDate>,
+    pub after: Option<NaiveDate>,
+    pub output: Option<String>,
+}
+
+impl ExportCommand {
+    pub fn execute(&self, connection: &mut SqliteConnection, term: &Term) -> Result<usize> {
+        let account = self
+            .account_query
+            .get_one(connection, true)
+            .ok_or_else(|| anyhow!("Account is not specified exactly!"))?;
+        let query = TransactionQuery {
+            limit: i64::MAX,
+            txid_filter: None,
+            account_filter: Some(account.guid.clone()),
+            description_filter: None,
+            memo_filter: None,
+            before_filter: self.before,
+            after_filter: self.after,
+        };
+        let mut rows = query.execute(connection);
+        rows.sort_by_key(|(_, tx)| tx.posting());
+        let counter_splits = load_counter_splits(connection, &account, &rows)?;
+
+        let content = write_qif(&account, &rows, &counter_splits);
+        match &self.output {
+            Some(file) => fs::write(file, content)?,
+            None => print!("{}", content),
+        }
+        term.write_line(&format!(
+            "Exported {} splits from {}",
+            style(rows.len()).cyan(),
+            style(&account).blue()
+        ))?;
+        Ok(rows.len())
+    }
+}
+
+fn qif_type(account: &Account) -> &'static str {
+    match account.account_type.as_str() {
+        "CASH" => "Cash",
+        "CREDIT" => "CCard",
+        "ASSET" => "Oth A",
+        "LIABILITY" => "Oth L",
+        _ => "Bank",
+    }
+}
+
+fn write_qif(
+    account: &Account,
+    rows: &[(Split, Transaction)],
+    counter_splits: &BTreeMap<String, Vec<(Split, String)>>,
+) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "!Type:{}", qif_type(account));
+    for (split, tx) in rows {
+        if let Some(post_date) = tx.posting() {
+            let _ = writeln!(out, "D{}", post_date.format("%m/%d/%Y"));
+        }
+        let _ = writeln!(out, "T{}", split.get_quantity_as_decimal().normalize());
+        match split.reconcile_state.as_str() {
+            "c" => out.push_str("C*\n"),
+            "y" => out.push_str("CX\n"),
+            _ => {}
+        }
+        if let Some(description) = tx.description.as_ref().filter(|d| !d.is_empty()) {
+            let _ = writeln!(out, "P{}", description);
+        }
+        if !split.memo.is_empty() {
+            let _ = writeln!(out, "M{}", split.memo);
+        }
+        match counter_splits.get(&split.tx_guid).map(Vec::as_slice) {
+            Some([(_, name)]) => {
+                let _ = writeln!(out, "L{}", name);
+            }
+            Some(others) if !others.is_empty() => {
+                // the split amounts are from the viewpoint of the exported account, their values
+                // are in the currency of the transaction, so they add up to the total
+                for (other, name) in others {
+                    let _ = writeln!(out, "S{}", name);
+                    if !other.memo.is_empty() {
+                        let _ = writeln!(out, "E{}", other.memo);
+                    }
+                    let _ = writeln!(out, "${}", (-other.get_value_as_decimal()).normalize());
+                }
+            }
+            _ => {}
+        }
+        out.push_str("^\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{account, booked_on};
+
+    fn split(guid: &str, account: &str, value: i64, quantity: i64, memo: &str) -> Split {
+        Split {
+            guid: guid.to_owned(),
+            tx_guid: "t1".to_owned(),
+            account_guid: account.to_owned(),
+            memo: memo.to_owned(),
+            action: String::new(),
+            reconcile_state: "c".to_owned(),
+            reconcile_date: None,
+            value_num: value,
+            value_denom: 100,
+            quantity_num: quantity,
+            quantity_denom: 100,
+            lot_guid: None,
+        }
+    }
+
+    fn rows() -> Vec<(Split, Transaction)> {
+        let (_, transaction) = booked_on("t1", "2024-01-31", -120, "Hardware store");
+        vec![(split("s1", "bank", -12000, -12000, "Drill"), transaction)]
+    }
+
+    #[test]
+    fn test_write_qif_with_one_counter_account() {
+        let counter_splits = BTreeMap::from([(
+            "t1".to_owned(),
+            vec![(
+                split("s2", "tools", 12000, 12000, ""),
+                "Expenses:Tools".to_owned(),
+            )],
+        )]);
+        assert_eq!(
+            write_qif(&account("bank", "Bank", "eur"), &rows(), &counter_splits),
+            "!Type:Bank\nD01/31/2024\nT-120\nC*\nPHardware store\nMDrill\nLExpenses:Tools\n^\n"
+        );
+    }
+
+    #[test]
+    fn test_write_qif_with_splits() {
+        // the foreign currency split is written in the currency of the transaction, like the total
+        let counter_splits = BTreeMap::from([(
+            "t1".to_owned(),
+            vec![
+                (
+                    split("s2", "tools", 10000, 10000, "Drill"),
+                    "Expenses:Tools".to_owned(),
+                ),
+                (
+                    split("s3", "usd", 2000, 2150, ""),
+                    "Assets:Dollars".to_owned(),
+                ),
+            ],
+        )]);
+        assert_eq!(
+            write_qif(&account("bank", "Bank", "eur"), &rows(), &counter_splits),
+            "!Type:Bank\nD01/31/2024\nT-120\nC*\nPHardware store\nMDrill\n\
+             SExpenses:Tools\nEDrill\n$-100\nSAssets:Dollars\n$-20\n^\n"
+        );
+    }
+}
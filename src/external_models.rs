@@ -1,14 +1,19 @@
+use std::cell::{Cell, RefCell};
 use std::fmt;
-use std::fs::File;
-use std::{cell::RefCell, io::BufReader};
+use std::fs::{self, File};
+use std::io::BufReader;
 
 use anyhow::Result;
 use calamine::{Data, Range, Reader, Sheets, open_workbook_auto};
 use chrono::NaiveDate;
+use clap::ValueEnum;
 use console::{Term, style};
 use rust_decimal::Decimal;
+use serde::Deserialize;
 
 use crate::models::{Split, Transaction};
+use crate::readers::csv::{CsvOptions, read_csv};
+use crate::similarity::similarity;
 
 #[derive(Debug, Clone)]
 pub struct ExternalTransaction {
@@ -21,6 +26,8 @@ pub struct ExternalTransaction {
     pub other_account_name: Option<String>,
     pub textual_date: Option<NaiveDate>,
     pub transaction_fee: Option<Decimal>,
+    // the identifier assigned by the bank, like the FITID of an OFX statement
+    pub reference: Option<String>,
 }
 
 impl fmt::Display for ExternalTransaction {
@@ -51,18 +58,104 @@ impl fmt::Display for ExternalTransaction {
     }
 }
 
+// A date of the statement rows, which can be used for matching
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum DateField {
+    Date,
+    BookingDate,
+    TextualDate,
+}
+
 #[derive(Copy, Clone)]
 pub enum Matching {
     ByBooking,
     BySpending,
+    Field(DateField),
+}
+
+// How many days the booked transaction can be earlier or later than the matching date
+// of the statement row, the banks usually book the card payments a few days later
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DateWindow {
+    pub days_before: u32,
+    pub days_after: u32,
+}
+
+impl Default for DateWindow {
+    fn default() -> Self {
+        DateWindow {
+            days_before: 10,
+            days_after: 10,
+        }
+    }
+}
+
+// How much the booked amount can differ from the amount of the statement row,
+// the larger of the absolute and the percentage limit is used, a refund never matches a payment
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AmountTolerance {
+    pub absolute: Decimal,
+    pub percent: Decimal,
+}
+
+impl AmountTolerance {
+    pub fn accepts(&self, expected: Decimal, actual: Decimal) -> bool {
+        let allowed = self
+            .absolute
+            .max(expected.abs() * self.percent / Decimal::ONE_HUNDRED);
+        let opposite = !expected.is_zero()
+            && !actual.is_zero()
+            && expected.is_sign_negative() != actual.is_sign_negative();
+        !opposite && (expected - actual).abs() <= allowed
+    }
+}
+
+// The date matching settings of a format, or from the command line, like
+//
+// [matching]
+// date = "booking_date"
+// days_before = 5
+// days_after = 0
+//
+// max_days sets both sides of the window, the days_before and days_after override it
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchingSettings {
+    pub date: Option<DateField>,
+    pub max_days: Option<u32>,
+    pub days_before: Option<u32>,
+    pub days_after: Option<u32>,
+}
+
+impl MatchingSettings {
+    // The window, where every side is taken from these settings, or from the fallback ones
+    pub fn window(&self, fallback: &MatchingSettings) -> DateWindow {
+        let default = DateWindow::default();
+        DateWindow {
+            days_before: self
+                .days_before
+                .or(self.max_days)
+                .or(fallback.days_before.or(fallback.max_days))
+                .unwrap_or(default.days_before),
+            days_after: self
+                .days_after
+                .or(self.max_days)
+                .or(fallback.days_after.or(fallback.max_days))
+                .unwrap_or(default.days_after),
+        }
+    }
 }
 
 impl ExternalTransaction {
-    // TODO: make it configurable
     pub fn get_matching_date(&self, matching: Matching) -> Option<NaiveDate> {
         match matching {
             Matching::ByBooking => self.date,
             Matching::BySpending => self.textual_date.or(self.date),
+            Matching::Field(DateField::Date) => self.date,
+            Matching::Field(DateField::BookingDate) => self.booking_date,
+            Matching::Field(DateField::TextualDate) => self.textual_date,
         }
     }
 
@@ -98,15 +191,167 @@ pub struct ExternalTransactionList(
     pub Vec<ExternalTransaction>,
     pub Option<NaiveDate>,
     pub Option<NaiveDate>,
+    // the opening and closing balance of the statement, if the format has them
+    pub StatementBalances,
 );
 
+impl ExternalTransactionList {
+    pub fn new(transactions: Vec<ExternalTransaction>, matching: Matching) -> Self {
+        let (min, max) = SheetDefinition::find_min_max(&transactions, matching);
+        ExternalTransactionList(transactions, min, max, StatementBalances::default())
+    }
+
+    pub fn load_statement(
+        input_file: &str,
+        matching: Matching,
+        format: &dyn StatementParser,
+        term: &Term,
+    ) -> Result<Self> {
+        let content = fs::read(input_file)?;
+        let statement = format.parse_statement(&content)?;
+        term.write_line(&format!(
+            "found {} transaction in {}",
+            style(statement.transactions.len()).cyan(),
+            style(input_file).blue()
+        ))?;
+        let balances = StatementBalances {
+            opening: statement.opening_balance,
+            closing: statement.closing_balance,
+        };
+        balances.display(term)?;
+        let mut list = ExternalTransactionList::new(statement.transactions, matching);
+        list.3 = balances;
+        Ok(list)
+    }
+}
+
+enum Workbook {
+    Spreadsheet(Box<Sheets<BufReader<File>>>),
+    // a CSV file is handled as a workbook with a single sheet, named after the file
+    Csv(String, Range<Data>),
+}
+
 pub struct SheetDefinition {
     //    input_file: String,
-    workbook: Sheets<BufReader<File>>,
+    workbook: Workbook,
 }
 
 pub trait SheetParser {
+    // How likely the sheet is in this format, between 0 and 100
+    fn sniff(&self, range: &Range<Data>) -> u32;
+    // Rejects the sheets, which can't be in this format
+    fn validate(&self, _range: &Range<Data>) -> Result<()> {
+        Ok(())
+    }
     fn parse_sheet(&self, range: &Range<Data>) -> Vec<ExternalTransaction>;
+    // The opening and closing balance, if the sheet has them
+    fn balances(&self, _range: &Range<Data>) -> StatementBalances {
+        StatementBalances::default()
+    }
+    // The date matching preferred by the format
+    fn matching(&self) -> MatchingSettings {
+        MatchingSettings::default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+    pub date: Option<NaiveDate>,
+    pub amount: Decimal,
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.date {
+            Some(date) => write!(f, "{} at {}", self.amount, date.format("%Y-%m-%d")),
+            None => write!(f, "{}", self.amount),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatementBalances {
+    pub opening: Option<Balance>,
+    pub closing: Option<Balance>,
+}
+
+impl StatementBalances {
+    pub fn is_empty(&self) -> bool {
+        self.opening.is_none() && self.closing.is_none()
+    }
+
+    // The balance before the first and after the last row, from the (date, amount, balance after
+    // the row) of each row. The rows can be in either order, the order is the one, where the
+    // balances follow each other, as the dates are often the same, or ordered otherwise.
+    pub fn from_running_balances(mut rows: Vec<(Option<NaiveDate>, Decimal, Decimal)>) -> Self {
+        let links = |rows: &[(Option<NaiveDate>, Decimal, Decimal)]| {
+            rows.windows(2)
+                .filter(|pair| pair[1].2 == pair[0].2 + pair[1].1)
+                .count()
+        };
+        let forward = links(&rows);
+        rows.reverse();
+        let backward = links(&rows);
+        if rows.len() > 1 && forward == 0 && backward == 0 {
+            // the balances don't follow each other in any order
+            return StatementBalances::default();
+        }
+        if backward <= forward {
+            rows.reverse();
+        }
+        StatementBalances {
+            opening: rows.first().map(|(date, amount, balance)| Balance {
+                date: *date,
+                amount: balance - amount,
+            }),
+            closing: rows.last().map(|(date, _, balance)| Balance {
+                date: *date,
+                amount: *balance,
+            }),
+        }
+    }
+
+    fn display(&self, term: &Term) -> Result<()> {
+        if let Some(balance) = &self.opening {
+            term.write_line(&format!("Opening balance: {}", style(balance).cyan()))?;
+        }
+        if let Some(balance) = &self.closing {
+            term.write_line(&format!("Closing balance: {}", style(balance).cyan()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Statement {
+    pub transactions: Vec<ExternalTransaction>,
+    pub opening_balance: Option<Balance>,
+    pub closing_balance: Option<Balance>,
+}
+
+// Statements which are not spreadsheets, like OFX files
+pub trait StatementParser {
+    // How likely the file is in this format, between 0 and 100
+    fn sniff(&self, content: &[u8]) -> u32;
+    fn parse_statement(&self, content: &[u8]) -> Result<Statement>;
+    // The date matching preferred by the format
+    fn matching(&self) -> MatchingSettings {
+        MatchingSettings::default()
+    }
+}
+
+pub enum StatementFormat {
+    Sheet(Box<dyn SheetParser>),
+    Statement(Box<dyn StatementParser>),
+}
+
+impl StatementFormat {
+    pub fn matching(&self) -> MatchingSettings {
+        match self {
+            StatementFormat::Sheet(parser) => parser.matching(),
+            StatementFormat::Statement(parser) => parser.matching(),
+        }
+    }
 }
 
 impl SheetDefinition {
@@ -114,10 +359,34 @@ impl SheetDefinition {
         let workbook = open_workbook_auto(input_file)?; //.expect("Cannot open file");
         Ok(SheetDefinition {
             // input_file,
-            workbook,
+            workbook: Workbook::Spreadsheet(Box::new(workbook)),
+        })
+    }
+
+    pub fn from_csv(input_file: &str, options: &CsvOptions) -> Result<Self> {
+        let range = read_csv(input_file, options)?;
+        Ok(SheetDefinition {
+            workbook: Workbook::Csv(input_file.to_owned(), range),
         })
     }
 
+    // The name and the content of the given sheet, or the first one, if no name is specified
+    pub fn range(&mut self, maybe_sheet_name: Option<String>) -> (String, Option<Range<Data>>) {
+        let sheet_name = match (maybe_sheet_name, &self.workbook) {
+            (_, Workbook::Csv(file_name, _)) => file_name.clone(),
+            (Some(name), _) => name,
+            (None, Workbook::Spreadsheet(workbook)) => {
+                let sheet_names = workbook.sheet_names();
+                sheet_names.first().cloned().unwrap_or_default()
+            }
+        };
+        let found_sheet = match &mut self.workbook {
+            Workbook::Spreadsheet(workbook) => workbook.worksheet_range(&sheet_name).ok(),
+            Workbook::Csv(_, range) => Some(range.clone()),
+        };
+        (sheet_name, found_sheet)
+    }
+
     pub fn load(
         &mut self,
         maybe_sheet_name: Option<String>,
@@ -125,23 +394,21 @@ impl SheetDefinition {
         format: &dyn SheetParser,
         term: &Term,
     ) -> Result<ExternalTransactionList> {
-        let sheet_name = match maybe_sheet_name {
-            Some(name) => name,
-            None => {
-                let sheet_names = self.workbook.sheet_names();
-                sheet_names.first().unwrap().to_owned()
-            }
-        };
-        if let Ok(sheet) = self.workbook.worksheet_range(&sheet_name) {
+        let (sheet_name, found_sheet) = self.range(maybe_sheet_name);
+        if let Some(sheet) = found_sheet {
             term.write_line(&format!("found sheet '{}'", style(&sheet_name).blue()))?;
+            format.validate(&sheet)?;
             let trans = format.parse_sheet(&sheet);
             term.write_line(&format!(
                 "found {} transaction on sheet {}",
                 style(trans.len()).cyan(),
                 style(&sheet_name).blue()
             ))?;
-            let (min, max) = SheetDefinition::find_min_max(&trans, matching);
-            Ok(ExternalTransactionList(trans, min, max))
+            let balances = format.balances(&sheet);
+            balances.display(term)?;
+            let mut list = ExternalTransactionList::new(trans, matching);
+            list.3 = balances;
+            Ok(list)
         } else {
             term.write_line(&format!(
                 "Sheet '{}' not found, no transactions will be imported!",
@@ -172,7 +439,12 @@ impl SheetDefinition {
 pub struct TransactionPairing {
     transaction: Transaction,
     split: Split,
-    external: RefCell<Option<ExternalTransaction>>,
+    // more statement rows can add up to one booked transaction, with their index
+    external: RefCell<Vec<(usize, ExternalTransaction)>>,
+    // another pairing would have been just as good
+    ambiguous: Cell<bool>,
+    // the booked transactions and statement rows matched together by their sum
+    group: Cell<Option<usize>>,
     amount: Decimal,
 }
 
@@ -182,21 +454,91 @@ impl TransactionPairing {
         TransactionPairing {
             transaction: pair.1,
             split: pair.0,
-            external: RefCell::new(None),
+            external: RefCell::new(Vec::new()),
+            ambiguous: Cell::new(false),
+            group: Cell::new(None),
             amount,
         }
     }
-    pub fn is_equal_amount(&self, amount: Decimal) -> bool {
-        amount.normalize() == self.amount
+    pub fn is_close_amount(&self, amount: Decimal, tolerance: &AmountTolerance) -> bool {
+        tolerance.accepts(self.amount, amount)
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.amount
     }
 
     pub fn is_not_matched(&self) -> bool {
-        self.external.borrow().is_none()
+        self.external.borrow().is_empty()
     }
 
-    pub fn pair_with(&self, external_trans: &ExternalTransaction) {
+    pub fn pair_with(&self, row: usize, external_trans: &ExternalTransaction) {
         let mut inner = self.external.borrow_mut();
-        *inner = Some(external_trans.to_owned());
+        inner.push((row, external_trans.to_owned()));
+    }
+
+    // Forgets the paired statement rows, with the group and the ambiguity of the pairing
+    pub fn unpair(&self) {
+        self.external.borrow_mut().clear();
+        self.ambiguous.set(false);
+        self.group.set(None);
+    }
+
+    pub fn split(&self) -> &Split {
+        &self.split
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn externals(&self) -> Vec<ExternalTransaction> {
+        self.external
+            .borrow()
+            .iter()
+            .map(|(_, external)| external.clone())
+            .collect()
+    }
+
+    // The indexes of the paired statement rows
+    pub fn rows(&self) -> Vec<usize> {
+        self.external.borrow().iter().map(|(row, _)| *row).collect()
+    }
+
+    pub fn set_group(&self, group: usize) {
+        self.group.set(Some(group));
+    }
+
+    pub fn group(&self) -> Option<usize> {
+        self.group.get()
+    }
+
+    pub fn mark_ambiguous(&self) {
+        self.ambiguous.set(true);
+    }
+
+    pub fn is_ambiguous(&self) -> bool {
+        self.ambiguous.get()
+    }
+
+    // The best similarity between the description or the other account of the statement row,
+    // and the description or the memo of the booked transaction
+    pub fn similarity_to(&self, external: &ExternalTransaction) -> f64 {
+        let external_texts = [&external.description, &external.other_account_name];
+        let booked_texts = [
+            self.transaction.description.as_ref(),
+            Some(&self.split.memo),
+        ];
+        external_texts
+            .into_iter()
+            .flatten()
+            .flat_map(|external_text| {
+                booked_texts
+                    .into_iter()
+                    .flatten()
+                    .map(move |booked_text| similarity(external_text, booked_text))
+            })
+            .fold(0.0, f64::max)
     }
 }
 
@@ -205,3 +547,68 @@ impl fmt::Display for TransactionPairing {
         write!(f, "{} - {}", self.transaction, self.split)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_tolerance() {
+        let amount = |value: i64| Decimal::from(value);
+        let tolerance = |absolute: i64, percent: i64| AmountTolerance {
+            absolute: amount(absolute),
+            percent: amount(percent),
+        };
+        // exact by default
+        assert!(AmountTolerance::default().accepts(amount(-1000), amount(-1000)));
+        assert!(!AmountTolerance::default().accepts(amount(-1000), amount(-1001)));
+        // the absolute limit
+        assert!(tolerance(5, 0).accepts(amount(-1000), amount(-1005)));
+        assert!(!tolerance(5, 0).accepts(amount(-1000), amount(-1006)));
+        // the percent of the expected amount
+        assert!(tolerance(0, 2).accepts(amount(-1000), amount(-980)));
+        assert!(!tolerance(0, 2).accepts(amount(-1000), amount(-979)));
+        // the larger of the two
+        assert!(tolerance(30, 2).accepts(amount(-1000), amount(-1030)));
+        assert!(tolerance(5, 2).accepts(amount(-1000), amount(-1020)));
+        assert!(!tolerance(5, 2).accepts(amount(-1000), amount(-1021)));
+        // a refund is not a payment, and the other way around
+        assert!(!tolerance(0, 200).accepts(amount(-1000), amount(1000)));
+        assert!(tolerance(5, 0).accepts(amount(1000), amount(996)));
+    }
+
+    #[test]
+    fn test_running_balances() {
+        let day = NaiveDate::from_ymd_opt(2024, 1, 5);
+        let row = |amount: i64, balance: i64| (day, Decimal::from(amount), Decimal::from(balance));
+        let balances = |opening: i64, closing: i64| StatementBalances {
+            opening: Some(Balance {
+                date: day,
+                amount: Decimal::from(opening),
+            }),
+            closing: Some(Balance {
+                date: day,
+                amount: Decimal::from(closing),
+            }),
+        };
+        // on the same day, only the balances tell the order
+        let oldest_first = vec![row(-200, 800), row(500, 1300), row(-100, 1200)];
+        assert_eq!(
+            StatementBalances::from_running_balances(oldest_first.clone()),
+            balances(1000, 1200)
+        );
+        let newest_first = oldest_first.into_iter().rev().collect();
+        assert_eq!(
+            StatementBalances::from_running_balances(newest_first),
+            balances(1000, 1200)
+        );
+        assert_eq!(
+            StatementBalances::from_running_balances(vec![row(-200, 800)]),
+            balances(1000, 800)
+        );
+        assert!(
+            StatementBalances::from_running_balances(vec![row(-200, 800), row(-100, 500)])
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dotenv::dotenv;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::external_models::MatchingSettings;
+use crate::formats::{ColumnMapping, ColumnRef, DatePattern, RowFilter};
+use crate::readers::csv::CsvOptions;
+
+// User defined statement format, for example:
+//
+// skip_rows = 1
+// decimal_comma = true
+// date_format = "dotted"
+//
+// [columns]
+// date = "Értéknap"
+// amount = 4
+// description = "Közlemény"
+//
+// [filter]
+// column = 0
+// matches = "^[0-9]"
+//
+// [csv]
+// delimiter = ";"
+// encoding = "windows-1250"
+//
+// [matching]
+// date = "booking_date"
+// days_before = 5
+// days_after = 0
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatFile {
+    #[serde(default)]
+    pub skip_rows: usize,
+    #[serde(default)]
+    pub decimal_comma: bool,
+    pub date_format: Option<DatePattern>,
+    pub booking_date_format: Option<DatePattern>,
+    pub columns: FormatColumns,
+    pub filter: Option<FormatFilter>,
+    pub csv: Option<FormatCsv>,
+    #[serde(default)]
+    pub matching: MatchingSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatColumns {
+    pub date: Option<ColumnRef>,
+    pub booking_date: Option<ColumnRef>,
+    pub amount: ColumnRef,
+    pub fee: Option<ColumnRef>,
+    pub category: Option<ColumnRef>,
+    pub description: Option<ColumnRef>,
+    pub other_account: Option<ColumnRef>,
+    pub other_account_name: Option<ColumnRef>,
+    pub balance: Option<ColumnRef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatFilter {
+    pub column: ColumnRef,
+    pub matches: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormatCsv {
+    pub delimiter: Option<char>,
+    pub quote: Option<char>,
+    pub encoding: Option<String>,
+}
+
+impl FormatFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read format file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid format file {}", path.display()))
+    }
+
+    pub fn to_mapping(&self) -> Result<ColumnMapping> {
+        let columns = &self.columns;
+        let mut mapping = ColumnMapping::new(columns.amount.clone());
+        mapping.date = columns.date.clone();
+        mapping.booking_date = columns.booking_date.clone();
+        mapping.fee = columns.fee.clone();
+        mapping.category = columns.category.clone();
+        mapping.description = columns.description.clone();
+        mapping.other_account = columns.other_account.clone();
+        mapping.other_account_name = columns.other_account_name.clone();
+        mapping.balance = columns.balance.clone();
+        mapping.date_format = self.date_format.clone().unwrap_or_default();
+        mapping.booking_date_format = self
+            .booking_date_format
+            .clone()
+            .unwrap_or_else(|| mapping.date_format.clone());
+        mapping.decimal_comma = self.decimal_comma;
+        mapping.skip_rows = self.skip_rows;
+        mapping.filter = match &self.filter {
+            Some(filter) => Some(RowFilter {
+                column: filter.column.clone(),
+                pattern: filter
+                    .matches
+                    .as_ref()
+                    .map(|pattern| Regex::new(pattern))
+                    .transpose()?,
+            }),
+            None => None,
+        };
+        mapping.matching = self.matching;
+        Ok(mapping)
+    }
+
+    // The CSV settings of the format file, the command line parameters take precedence
+    pub fn csv_options(
+        &self,
+        delimiter: Option<char>,
+        quote: Option<char>,
+        skip_rows: Option<usize>,
+        encoding: Option<&str>,
+    ) -> Result<Option<CsvOptions>> {
+        match &self.csv {
+            Some(csv) => Ok(Some(CsvOptions::new(
+                delimiter.or(csv.delimiter),
+                quote.or(csv.quote),
+                skip_rows,
+                encoding.or(csv.encoding.as_deref()),
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// Directories searched for <name>.toml format files: $FINANC_FORMATS, and ~/.config/financ/formats
+fn format_directories() -> Vec<PathBuf> {
+    dotenv().ok();
+    let mut result = Vec::new();
+    if let Ok(dir) = env::var("FINANC_FORMATS") {
+        result.push(PathBuf::from(dir));
+    }
+    if let Ok(home) = env::var("HOME") {
+        result.push(Path::new(&home).join(".config/financ/formats"));
+    }
+    result
+}
+
+// The name and path of every format file in the format directories
+pub fn list_format_files() -> Vec<(String, PathBuf)> {
+    let mut result: Vec<(String, PathBuf)> = format_directories()
+        .into_iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            Some((name, path))
+        })
+        .collect();
+    result.sort();
+    // the first directory takes precedence, like in find_format_file
+    result.dedup_by(|later, earlier| later.0 == earlier.0);
+    result
+}
+
+pub fn find_format_file(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    format_directories()
+        .into_iter()
+        .map(|dir| dir.join(format!("{}.toml", name)))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external_models::{Balance, DateField, DateWindow, SheetParser};
+    use crate::readers::csv::parse_csv;
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    const FORMAT: &str = r#"
+        decimal_comma = true
+        date_format = "dotted"
+
+        [columns]
+        date = "Date"
+        amount = 2
+        description = "Details"
+
+        [filter]
+        column = "Type"
+        matches = "^CARD"
+    "#;
+
+    #[test]
+    fn test_format_file_with_headers() {
+        let format: FormatFile = toml::from_str(FORMAT).unwrap();
+        let mapping = format.to_mapping().unwrap();
+        let range = parse_csv(
+            "Type;Date;Amount;Details\n\
+             CARD;2024.01.05.;-1 200,50;Shop\n\
+             TRANSFER;2024.01.06.;-100,00;Rent\n"
+                .as_bytes(),
+            &CsvOptions::new(Some(';'), None, None, None).unwrap(),
+        )
+        .unwrap();
+        let transactions = mapping.parse_sheet(&range);
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].date, NaiveDate::from_ymd_opt(2024, 1, 5));
+        assert_eq!(transactions[0].amount, Some(Decimal::new(-120050, 2)));
+        assert_eq!(transactions[0].description, Some("Shop".to_owned()));
+    }
+
+    #[test]
+    fn test_sniff_format_file() {
+        let format: FormatFile = toml::from_str(FORMAT).unwrap();
+        let mapping = format.to_mapping().unwrap();
+        let options = CsvOptions::new(Some(';'), None, None, None).unwrap();
+        let matching = parse_csv(
+            "Type;Date;Amount;Details\nCARD;2024.01.05.;-1 200,50;Shop\n".as_bytes(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(mapping.sniff(&matching), 100);
+        let other_headers = parse_csv(
+            "Kind;Day;Value\nCARD;2024.01.05.;-1 200,50\n".as_bytes(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(mapping.sniff(&other_headers), 0);
+    }
+
+    #[test]
+    fn test_matching_settings_of_format_file() {
+        let format: FormatFile = toml::from_str(&format!(
+            "{}\n[matching]\ndate = \"booking_date\"\nmax_days = 5\ndays_after = 0",
+            FORMAT
+        ))
+        .unwrap();
+        let matching = format.to_mapping().unwrap().matching();
+        assert_eq!(matching.date, Some(DateField::BookingDate));
+        let window = MatchingSettings::default().window(&matching);
+        assert_eq!((window.days_before, window.days_after), (5, 0));
+        // the command line overrides the sides it sets
+        let command_line = MatchingSettings {
+            days_before: Some(2),
+            ..MatchingSettings::default()
+        };
+        let window = command_line.window(&matching);
+        assert_eq!((window.days_before, window.days_after), (2, 0));
+        assert_eq!(
+            MatchingSettings::default().window(&MatchingSettings::default()),
+            DateWindow::default()
+        );
+    }
+
+    #[test]
+    fn test_balances_of_format_file() {
+        let format: FormatFile = toml::from_str(&FORMAT.replace(
+            "description = \"Details\"",
+            "description = \"Details\"\nbalance = \"Balance\"",
+        ))
+        .unwrap();
+        let mapping = format.to_mapping().unwrap();
+        // the newest row is the first one
+        let range = parse_csv(
+            "Type;Date;Amount;Details;Balance\n\
+             CARD;2024.01.07.;-200,00;Shop;800,00\n\
+             CARD;2024.01.05.;-1 000,00;Shop;1 000,00\n"
+                .as_bytes(),
+            &CsvOptions::new(Some(';'), None, None, None).unwrap(),
+        )
+        .unwrap();
+        let balances = mapping.balances(&range);
+        assert_eq!(
+            balances.opening,
+            Some(Balance {
+                date: NaiveDate::from_ymd_opt(2024, 1, 5),
+                amount: Decimal::from(2000),
+            })
+        );
+        assert_eq!(
+            balances.closing,
+            Some(Balance {
+                date: NaiveDate::from_ymd_opt(2024, 1, 7),
+                amount: Decimal::from(800),
+            })
+        );
+    }
+}
@@ -1,10 +1,25 @@
-use crate::external_models::{ExternalTransaction, SheetParser};
+use crate::external_models::{
+    ExternalTransaction, MatchingSettings, SheetParser, StatementBalances,
+};
 use crate::sheets::{
-    cell_to_date, cell_to_datetime, cell_to_decimal, cell_to_english_date, cell_to_german_date,
-    cell_to_iso_date, cell_to_string,
+    cell_to_date, cell_to_date_raw, cell_to_datetime, cell_to_decimal, cell_to_english_date,
+    cell_to_german_date, cell_to_iso_date, cell_to_localized_decimal, cell_to_string,
 };
 use crate::utils::extract_date;
+use anyhow::{Context, Result};
 use calamine::{Data, DataType, Range};
+use chrono::NaiveDate;
+use regex::Regex;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+// How the amounts are stored in the sheet: as number cells in the spreadsheets, or as text
+// with the decimal separator of the bank in the CSV exports
+#[derive(Debug, Clone, Copy)]
+enum Amounts {
+    Numbers,
+    Text,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum SheetFormat {
@@ -28,33 +43,141 @@ impl SheetFormat {
             _ => None,
         }
     }
+
+    pub const ALL: [SheetFormat; 6] = [
+        SheetFormat::Otp,
+        SheetFormat::Otp2020,
+        SheetFormat::Granit,
+        SheetFormat::BankAustria,
+        SheetFormat::Transferwise,
+        SheetFormat::Magnet,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SheetFormat::Otp => "otp",
+            SheetFormat::Otp2020 => "otp2020",
+            SheetFormat::Granit => "granit",
+            SheetFormat::BankAustria => "bankaustria",
+            SheetFormat::Transferwise => "transferwise",
+            SheetFormat::Magnet => "magnet",
+        }
+    }
+
+    // (header rows, minimum number of columns, date column, amount column) of the layout
+    fn layout(&self) -> (usize, usize, usize, usize) {
+        match self {
+            SheetFormat::Otp => (0, 9, 2, 4),
+            SheetFormat::Otp2020 => (0, 8, 2, 4),
+            SheetFormat::Granit => (0, 12, 4, 1),
+            SheetFormat::BankAustria => (1, 13, 1, 6),
+            SheetFormat::Transferwise => (1, 15, 1, 2),
+            SheetFormat::Magnet => (1, 7, 1, 6),
+        }
+    }
+
+    // CSV exports have the amounts as text, only Transferwise uses decimal point
+    fn amount(&self, cell: &Data, amounts: Amounts) -> Option<Decimal> {
+        match amounts {
+            Amounts::Numbers => cell_to_decimal(cell),
+            Amounts::Text => {
+                cell_to_localized_decimal(cell, !matches!(self, SheetFormat::Transferwise))
+            }
+        }
+    }
+
+    // The rows kept by the parser
+    fn is_transaction(&self, row: &[Data], amounts: Amounts) -> bool {
+        let amount_column = match self {
+            SheetFormat::Otp | SheetFormat::Otp2020 => return row[0] != Data::Empty,
+            SheetFormat::Granit => 1,
+            SheetFormat::BankAustria | SheetFormat::Magnet => 6,
+            SheetFormat::Transferwise => 2,
+        };
+        match amounts {
+            Amounts::Numbers => row[amount_column].is_float(),
+            Amounts::Text => self.amount(&row[amount_column], amounts).is_some(),
+        }
+    }
+
+    // The column of the running balance after the row
+    fn balance_column(&self) -> Option<usize> {
+        match self {
+            SheetFormat::Transferwise => Some(6),
+            _ => None,
+        }
+    }
+
+    // The date in the date column of the layout
+    fn date(&self, cell: &Data) -> Option<NaiveDate> {
+        match self {
+            SheetFormat::Otp | SheetFormat::Magnet => cell_to_date(cell),
+            SheetFormat::Otp2020 => cell_to_datetime(cell).map(|datetime| datetime.date()),
+            SheetFormat::Granit => cell_to_iso_date(cell),
+            SheetFormat::BankAustria => cell_to_german_date(cell),
+            SheetFormat::Transferwise => cell_to_english_date(cell),
+        }
+    }
+
+    fn is_expected_date(&self, cell: &Data) -> bool {
+        // the formats are told apart by their dates as text, an excel date cell fits any of them
+        !matches!(cell, Data::DateTime(_)) && self.date(cell).is_some()
+    }
 }
 
-impl SheetParser for SheetFormat {
-    fn parse_sheet(&self, range: &Range<Data>) -> Vec<ExternalTransaction> {
+impl SheetFormat {
+    fn sniff_rows(&self, range: &Range<Data>, amounts: Amounts) -> u32 {
+        let (skip, width, date, amount) = self.layout();
+        if range.width() < width {
+            return 0;
+        }
+        score_rows(range, skip, |row| {
+            self.is_transaction(row, amounts)
+                && self.is_expected_date(&row[date])
+                && self.amount(&row[amount], amounts).is_some()
+        })
+    }
+
+    fn check_width(&self, range: &Range<Data>) -> Result<()> {
+        let (_, width, _, _) = self.layout();
+        ensure!(
+            range.width() >= width,
+            "The sheet has {} columns, the {} format needs at least {}!",
+            range.width(),
+            self.name(),
+            width
+        );
+        Ok(())
+    }
+
+    fn parse_rows(&self, range: &Range<Data>, amounts: Amounts) -> Vec<ExternalTransaction> {
+        if self.check_width(range).is_err() {
+            return Vec::new();
+        }
         match self {
             SheetFormat::Otp => range
                 .rows()
-                .filter(|row| row[0] != Data::Empty)
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let descrip = cell_to_string(&row[8]);
                     let parsed_date = extract_date(&descrip);
                     ExternalTransaction {
                         date: cell_to_date(&row[2]),
                         booking_date: cell_to_date(&row[3]),
-                        amount: cell_to_decimal(&row[4]),
+                        amount: self.amount(&row[4], amounts),
                         category: cell_to_string(&row[1]),
                         description: descrip,
                         other_account: cell_to_string(&row[6]),
                         other_account_name: cell_to_string(&row[7]),
                         textual_date: parsed_date,
                         transaction_fee: None,
+                        reference: None,
                     }
                 })
                 .collect(),
             SheetFormat::Otp2020 => range
                 .rows()
-                .filter(|row| row[0] != Data::Empty)
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let spend_date = cell_to_datetime(&row[2]);
                     let description = cell_to_string(&row[7]);
@@ -62,19 +185,20 @@ impl SheetParser for SheetFormat {
                     ExternalTransaction {
                         date: spend_date.map(|datetime| datetime.date()),
                         booking_date: cell_to_date(&row[3]),
-                        amount: cell_to_decimal(&row[4]),
+                        amount: self.amount(&row[4], amounts),
                         category: cell_to_string(&row[1]),
                         description,
                         other_account: cell_to_string(&row[5]),
                         other_account_name: cell_to_string(&row[6]),
                         textual_date: parsed_date,
                         transaction_fee: None,
+                        reference: None,
                     }
                 })
                 .collect(),
             SheetFormat::Granit => range
                 .rows()
-                .filter(|row| row[1].is_float())
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let date = cell_to_iso_date(&row[4]);
                     let other_account_name = cell_to_string(&row[7])
@@ -84,24 +208,25 @@ impl SheetParser for SheetFormat {
                     ExternalTransaction {
                         date,
                         booking_date: None,
-                        amount: cell_to_decimal(&row[1]),
+                        amount: self.amount(&row[1], amounts),
                         category: cell_to_string(&row[6]),
                         description: concat(&other_account_name, &comment),
                         other_account: cell_to_string(&row[8]),
                         other_account_name,
                         textual_date: None,
                         transaction_fee: None,
+                        reference: None,
                     }
                 })
                 .collect(),
             SheetFormat::BankAustria => range
                 .rows()
                 .skip(1)
-                .filter(|row| row[6].is_float())
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let date = cell_to_german_date(&row[1]);
                     let booking_date = cell_to_german_date(&row[1]);
-                    let amount = cell_to_decimal(&row[6]);
+                    let amount = self.amount(&row[6], amounts);
                     let other_account = if let Some(amount_value) = amount
                         && amount_value.is_sign_negative()
                     {
@@ -119,16 +244,17 @@ impl SheetParser for SheetFormat {
                         other_account_name: None,
                         textual_date: None,
                         transaction_fee: None,
+                        reference: None,
                     }
                 })
                 .collect(),
             SheetFormat::Transferwise => range
                 .rows()
                 .skip(1)
-                .filter(|row| row[2].is_float())
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let date = cell_to_english_date(&row[1]);
-                    let amount = cell_to_decimal(&row[2]);
+                    let amount = self.amount(&row[2], amounts);
                     let other_account_name =
                         cell_to_string(&row[13]).or_else(|| cell_to_string(&row[11]));
                     let other_account = cell_to_string(&row[12]);
@@ -142,19 +268,21 @@ impl SheetParser for SheetFormat {
                         other_account,
                         other_account_name,
                         textual_date: None,
-                        transaction_fee: cell_to_decimal(&row[14])
+                        transaction_fee: self
+                            .amount(&row[14], amounts)
                             .filter(|value| value.is_sign_positive()),
+                        reference: None,
                     }
                 })
                 .collect(),
             SheetFormat::Magnet => range
                 .rows()
                 .skip(1)
-                .filter(|row| row[6].is_float())
+                .filter(|row| self.is_transaction(row, amounts))
                 .map(|row| {
                     let date = cell_to_date(&row[1]);
                     let booking_date = cell_to_date(&row[2]);
-                    let amount = cell_to_decimal(&row[6]);
+                    let amount = self.amount(&row[6], amounts);
                     let other_account = cell_to_string(&row[4]);
                     let other_account_name = cell_to_string(&row[3]);
                     let description = cell_to_string(&row[5]);
@@ -169,11 +297,435 @@ impl SheetParser for SheetFormat {
                         other_account_name,
                         textual_date: None,
                         transaction_fee: None,
+                        reference: None,
                     }
                 })
                 .collect(),
         }
     }
+
+    fn running_balances(&self, range: &Range<Data>, amounts: Amounts) -> StatementBalances {
+        let Some(balance) = self.balance_column() else {
+            return StatementBalances::default();
+        };
+        if self.check_width(range).is_err() {
+            return StatementBalances::default();
+        }
+        let (skip, _, date, amount) = self.layout();
+        let rows = range
+            .rows()
+            .skip(skip)
+            .filter(|row| self.is_transaction(row, amounts))
+            .filter_map(|row| {
+                let amount = self.amount(&row[amount], amounts)?;
+                let balance = self.amount(&row[balance], amounts)?;
+                Some((self.date(&row[date]), amount, balance))
+            })
+            .collect();
+        StatementBalances::from_running_balances(rows)
+    }
+}
+
+impl SheetParser for SheetFormat {
+    fn sniff(&self, range: &Range<Data>) -> u32 {
+        self.sniff_rows(range, Amounts::Numbers)
+    }
+
+    fn validate(&self, range: &Range<Data>) -> Result<()> {
+        self.check_width(range)
+    }
+
+    fn parse_sheet(&self, range: &Range<Data>) -> Vec<ExternalTransaction> {
+        self.parse_rows(range, Amounts::Numbers)
+    }
+
+    fn balances(&self, range: &Range<Data>) -> StatementBalances {
+        self.running_balances(range, Amounts::Numbers)
+    }
+}
+
+// A built-in format, read from the CSV export of the bank instead of its spreadsheet
+#[derive(Debug, Clone, Copy)]
+pub struct CsvExport(pub SheetFormat);
+
+impl SheetParser for CsvExport {
+    fn sniff(&self, range: &Range<Data>) -> u32 {
+        self.0.sniff_rows(range, Amounts::Text)
+    }
+
+    fn validate(&self, range: &Range<Data>) -> Result<()> {
+        self.0.check_width(range)
+    }
+
+    fn parse_sheet(&self, range: &Range<Data>) -> Vec<ExternalTransaction> {
+        self.0.parse_rows(range, Amounts::Text)
+    }
+
+    fn balances(&self, range: &Range<Data>) -> StatementBalances {
+        self.0.running_balances(range, Amounts::Text)
+    }
+}
+
+// A column of the sheet, either by its index, or by its title in the header row
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Header(String),
+}
+
+impl ColumnRef {
+    fn parse(value: &str) -> Self {
+        match value.trim().parse() {
+            Ok(idx) => ColumnRef::Index(idx),
+            Err(_) => ColumnRef::Header(value.trim().to_owned()),
+        }
+    }
+
+    fn position(&self, headers: &[String]) -> Option<usize> {
+        match self {
+            ColumnRef::Index(idx) => Some(*idx),
+            ColumnRef::Header(title) => headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(title)),
+        }
+    }
+
+    fn resolve(&self, headers: &[String]) -> Option<usize> {
+        let found = self.position(headers);
+        if found.is_none()
+            && let ColumnRef::Header(title) = self
+        {
+            println!("Column '{}' not found in the header row", title);
+        }
+        found
+    }
+}
+
+// Either the name of one of the known date formats, or a chrono format string
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(from = "String")]
+pub enum DatePattern {
+    // yyyy.mm.dd.
+    Dotted,
+    // yyyy-mm-dd
+    #[default]
+    Iso,
+    // dd.mm.yyyy
+    German,
+    // dd-mm-yyyy
+    English,
+    Custom(String),
+}
+
+impl From<String> for DatePattern {
+    fn from(value: String) -> Self {
+        match value.to_lowercase().as_str() {
+            "dotted" => DatePattern::Dotted,
+            "iso" => DatePattern::Iso,
+            "german" => DatePattern::German,
+            "english" => DatePattern::English,
+            _ => DatePattern::Custom(value),
+        }
+    }
+}
+
+impl DatePattern {
+    pub fn parse(&self, cell: &Data) -> Option<NaiveDate> {
+        match self {
+            DatePattern::Dotted => cell_to_date(cell),
+            DatePattern::Iso => cell_to_iso_date(cell),
+            DatePattern::German => cell_to_german_date(cell),
+            DatePattern::English => cell_to_english_date(cell),
+            DatePattern::Custom(format) => cell_to_date_raw(cell, format),
+        }
+    }
+}
+
+// Rows are only processed, if the given column is not empty, and matches the pattern
+#[derive(Debug, Clone)]
+pub struct RowFilter {
+    pub column: ColumnRef,
+    pub pattern: Option<Regex>,
+}
+
+// Declarative description of a statement, where each field of the ExternalTransaction
+// is read from a given column, for example: "date=0,amount=3,description=Details"
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    pub date: Option<ColumnRef>,
+    pub booking_date: Option<ColumnRef>,
+    pub amount: ColumnRef,
+    pub fee: Option<ColumnRef>,
+    pub category: Option<ColumnRef>,
+    pub description: Option<ColumnRef>,
+    pub other_account: Option<ColumnRef>,
+    pub other_account_name: Option<ColumnRef>,
+    // the running balance after the row
+    pub balance: Option<ColumnRef>,
+    pub date_format: DatePattern,
+    pub booking_date_format: DatePattern,
+    pub decimal_comma: bool,
+    pub skip_rows: usize,
+    pub filter: Option<RowFilter>,
+    pub matching: MatchingSettings,
+}
+
+// The column indexes of a ColumnMapping, after the header names are looked up
+struct ResolvedColumns {
+    date: Option<usize>,
+    booking_date: Option<usize>,
+    amount: Option<usize>,
+    fee: Option<usize>,
+    category: Option<usize>,
+    description: Option<usize>,
+    other_account: Option<usize>,
+    other_account_name: Option<usize>,
+    balance: Option<usize>,
+    filter: Option<usize>,
+}
+
+impl ColumnMapping {
+    pub fn new(amount: ColumnRef) -> Self {
+        ColumnMapping {
+            date: None,
+            booking_date: None,
+            amount,
+            fee: None,
+            category: None,
+            description: None,
+            other_account: None,
+            other_account_name: None,
+            balance: None,
+            date_format: DatePattern::default(),
+            booking_date_format: DatePattern::default(),
+            decimal_comma: false,
+            skip_rows: 0,
+            filter: None,
+            matching: MatchingSettings::default(),
+        }
+    }
+
+    pub fn parse(spec: &str, date_format: Option<String>, decimal_comma: bool) -> Result<Self> {
+        let mut fields = Vec::new();
+        for pair in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .with_context(|| format!("Expected field=column, found '{}'!", pair))?;
+            fields.push((field.trim(), ColumnRef::parse(column)));
+        }
+        let amount = fields
+            .iter()
+            .find(|(field, _)| *field == "amount")
+            .map(|(_, column)| column.clone())
+            .context("The column mapping needs an amount column!")?;
+        let mut mapping = ColumnMapping::new(amount);
+        for (field, column) in fields {
+            match field {
+                "date" => mapping.date = Some(column),
+                "booking_date" => mapping.booking_date = Some(column),
+                "amount" => {}
+                "fee" => mapping.fee = Some(column),
+                "category" => mapping.category = Some(column),
+                "description" => mapping.description = Some(column),
+                "other_account" => mapping.other_account = Some(column),
+                "other_account_name" => mapping.other_account_name = Some(column),
+                "balance" => mapping.balance = Some(column),
+                other => return Err(anyhow!("Unknown field in the column mapping: '{}'!", other)),
+            }
+        }
+        if let Some(format) = date_format {
+            mapping.date_format = DatePattern::from(format);
+            mapping.booking_date_format = mapping.date_format.clone();
+        }
+        mapping.decimal_comma = decimal_comma;
+        Ok(mapping)
+    }
+
+    fn columns(&self) -> impl Iterator<Item = &ColumnRef> {
+        [
+            Some(&self.amount),
+            self.date.as_ref(),
+            self.booking_date.as_ref(),
+            self.fee.as_ref(),
+            self.category.as_ref(),
+            self.description.as_ref(),
+            self.other_account.as_ref(),
+            self.other_account_name.as_ref(),
+            self.balance.as_ref(),
+            self.filter.as_ref().map(|filter| &filter.column),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    fn uses_headers(&self) -> bool {
+        self.columns()
+            .any(|column| matches!(column, ColumnRef::Header(_)))
+    }
+
+    fn headers(&self, range: &Range<Data>) -> Vec<String> {
+        if self.uses_headers() {
+            range
+                .rows()
+                .nth(self.skip_rows)
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn resolve(&self, headers: &[String]) -> ResolvedColumns {
+        let resolve =
+            |column: &Option<ColumnRef>| column.as_ref().and_then(|col| col.resolve(headers));
+        ResolvedColumns {
+            date: resolve(&self.date),
+            booking_date: resolve(&self.booking_date),
+            amount: self.amount.resolve(headers),
+            fee: resolve(&self.fee),
+            category: resolve(&self.category),
+            description: resolve(&self.description),
+            other_account: resolve(&self.other_account),
+            other_account_name: resolve(&self.other_account_name),
+            balance: resolve(&self.balance),
+            filter: self
+                .filter
+                .as_ref()
+                .and_then(|filter| filter.column.resolve(headers)),
+        }
+    }
+
+    fn is_accepted(&self, row: &[Data], columns: &ResolvedColumns) -> bool {
+        if self.decimal_at(row, columns.amount).is_none() {
+            return false;
+        }
+        match &self.filter {
+            None => true,
+            Some(filter) => {
+                let value = columns
+                    .filter
+                    .and_then(|idx| row.get(idx))
+                    .filter(|cell| !cell.is_empty())
+                    .map(|cell| cell.to_string());
+                match (value, &filter.pattern) {
+                    (Some(text), Some(pattern)) => pattern.is_match(&text),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            }
+        }
+    }
+
+    fn string_at(row: &[Data], column: Option<usize>) -> Option<String> {
+        column.and_then(|idx| row.get(idx)).and_then(cell_to_string)
+    }
+
+    fn date_at(row: &[Data], column: Option<usize>, pattern: &DatePattern) -> Option<NaiveDate> {
+        column
+            .and_then(|idx| row.get(idx))
+            .and_then(|cell| pattern.parse(cell))
+    }
+
+    fn decimal_at(&self, row: &[Data], column: Option<usize>) -> Option<Decimal> {
+        column
+            .and_then(|idx| row.get(idx))
+            .and_then(|cell| cell_to_localized_decimal(cell, self.decimal_comma))
+    }
+}
+
+impl SheetParser for ColumnMapping {
+    fn sniff(&self, range: &Range<Data>) -> u32 {
+        let headers = self.headers(range);
+        if self
+            .columns()
+            .any(|column| column.position(&headers).is_none())
+        {
+            return 0;
+        }
+        let columns = self.resolve(&headers);
+        let header_rows = if self.uses_headers() { 1 } else { 0 };
+        score_rows(range, self.skip_rows + header_rows, |row| {
+            let date_cell = columns.date.and_then(|idx| row.get(idx));
+            let date_ok = match date_cell {
+                Some(cell) => self.date_format.parse(cell).is_some(),
+                None => columns.date.is_none(),
+            };
+            // rows dropped by the filter are still in the format
+            date_ok && self.decimal_at(row, columns.amount).is_some()
+        })
+    }
+
+    fn parse_sheet(&self, range: &Range<Data>) -> Vec<ExternalTransaction> {
+        let headers = self.headers(range);
+        let header_rows = if headers.is_empty() { 0 } else { 1 };
+        let columns = self.resolve(&headers);
+        range
+            .rows()
+            .skip(self.skip_rows + header_rows)
+            .filter(|row| self.is_accepted(row, &columns))
+            .map(|row| ExternalTransaction {
+                date: ColumnMapping::date_at(row, columns.date, &self.date_format),
+                booking_date: ColumnMapping::date_at(
+                    row,
+                    columns.booking_date,
+                    &self.booking_date_format,
+                ),
+                amount: self.decimal_at(row, columns.amount),
+                category: ColumnMapping::string_at(row, columns.category),
+                description: ColumnMapping::string_at(row, columns.description),
+                other_account: ColumnMapping::string_at(row, columns.other_account),
+                other_account_name: ColumnMapping::string_at(row, columns.other_account_name),
+                textual_date: None,
+                transaction_fee: self.decimal_at(row, columns.fee),
+                reference: None,
+            })
+            .collect()
+    }
+
+    fn balances(&self, range: &Range<Data>) -> StatementBalances {
+        let headers = self.headers(range);
+        let header_rows = if headers.is_empty() { 0 } else { 1 };
+        let columns = self.resolve(&headers);
+        if columns.balance.is_none() {
+            return StatementBalances::default();
+        }
+        let rows = range
+            .rows()
+            .skip(self.skip_rows + header_rows)
+            .filter(|row| self.is_accepted(row, &columns))
+            .filter_map(|row| {
+                let date =
+                    ColumnMapping::date_at(row, columns.booking_date, &self.booking_date_format)
+                        .or_else(|| ColumnMapping::date_at(row, columns.date, &self.date_format));
+                let amount = self.decimal_at(row, columns.amount)?;
+                let balance = self.decimal_at(row, columns.balance)?;
+                Some((date, amount, balance))
+            })
+            .collect();
+        StatementBalances::from_running_balances(rows)
+    }
+
+    fn matching(&self) -> MatchingSettings {
+        self.matching
+    }
+}
+
+const SNIFF_ROWS: usize = 30;
+
+// The percentage of the first, non-empty rows, which look like a transaction of the format
+fn score_rows(range: &Range<Data>, skip: usize, check: impl Fn(&[Data]) -> bool) -> u32 {
+    let sample: Vec<&[Data]> = range
+        .rows()
+        .skip(skip)
+        .filter(|row| row.iter().any(|cell| !cell.is_empty()))
+        .take(SNIFF_ROWS)
+        .collect();
+    if sample.is_empty() {
+        return 0;
+    }
+    let matching = sample.iter().filter(|row| check(row)).count();
+    (matching * 100 / sample.len()) as u32
 }
 
 fn concat(first: &Option<String>, second: &Option<String>) -> Option<String> {
@@ -212,3 +764,133 @@ fn cleanup_string(input: String) -> String {
         .replace("u:", "ü")
         .replace("o:", "ö")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::external_models::Balance;
+    use crate::readers::csv::{CsvOptions, parse_csv};
+    use calamine::{ExcelDateTime, ExcelDateTimeType};
+
+    fn csv_range(content: &str) -> Range<Data> {
+        let options = CsvOptions::new(Some(';'), None, None, None).unwrap();
+        parse_csv(content.as_bytes(), &options).unwrap()
+    }
+
+    #[test]
+    fn test_builtin_format_on_csv() {
+        let range = csv_range(
+            "Sorszám;Dátum;Értéknap;Partner;Számla;Közlemény;Összeg\n\
+             1;2024.01.05.;2024.01.06.;TESCO;1177;bevásárlás;\"-1 234,50\"\n\
+             2;2024.01.12.;2024.01.12.;Lidl;;;-3000\n",
+        );
+        let transactions = CsvExport(SheetFormat::Magnet).parse_sheet(&range);
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].amount, Some(Decimal::new(-123450, 2)));
+        assert_eq!(transactions[0].date, NaiveDate::from_ymd_opt(2024, 1, 5));
+        assert_eq!(transactions[1].amount, Some(Decimal::new(-3000, 0)));
+
+        // too narrow for the layout
+        assert!(
+            CsvExport(SheetFormat::Transferwise)
+                .validate(&range)
+                .is_err()
+        );
+        assert!(
+            CsvExport(SheetFormat::Transferwise)
+                .parse_sheet(&range)
+                .is_empty()
+        );
+
+        // the text cells of a spreadsheet are not amounts
+        assert!(SheetFormat::Magnet.parse_sheet(&range).is_empty());
+        let mut excel = Range::new((0, 0), (2, 6));
+        excel.set_value((1, 6), Data::Float(-1234.5));
+        excel.set_value((2, 6), Data::String("1234.50".to_owned()));
+        let transactions = SheetFormat::Magnet.parse_sheet(&excel);
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].amount, Some(Decimal::new(-12345, 1)));
+    }
+
+    #[test]
+    fn test_sniff_builtin_formats() {
+        let csv = csv_range(
+            "Sorszám;Dátum;Értéknap;Partner;Számla;Közlemény;Összeg\n\
+             1;2024.01.05.;2024.01.06.;TESCO;1177;bevásárlás;\"-1 234,50\"\n\
+             2;2024.01.12.;2024.01.12.;Lidl;;;-3000\n",
+        );
+        let mut excel = Range::new((0, 0), (1, 6));
+        excel.set_value((1, 1), Data::String("2024.01.05.".to_owned()));
+        excel.set_value((1, 6), Data::Float(-1234.5));
+        let scores = |sniff: &dyn Fn(SheetFormat) -> u32| -> Vec<(&str, u32)> {
+            SheetFormat::ALL
+                .iter()
+                .map(|format| (format.name(), sniff(*format)))
+                .filter(|(_, score)| *score > 0)
+                .collect()
+        };
+        assert_eq!(
+            scores(&|format| CsvExport(format).sniff(&csv)),
+            vec![("magnet", 100)]
+        );
+        assert_eq!(
+            scores(&|format| format.sniff(&excel)),
+            vec![("magnet", 100)]
+        );
+        // the sniffed rows are the parsed ones
+        assert_eq!(SheetFormat::Magnet.parse_sheet(&excel).len(), 1);
+    }
+
+    #[test]
+    fn test_excel_date_cells() {
+        // 2024-01-05 as an excel date
+        let date_cell = Data::DateTime(ExcelDateTime::new(
+            45296.0,
+            ExcelDateTimeType::DateTime,
+            false,
+        ));
+        let mut excel = Range::new((0, 0), (0, 2));
+        excel.set_value((0, 0), date_cell);
+        excel.set_value((0, 1), Data::Float(-500.0));
+        excel.set_value((0, 2), Data::Float(1500.0));
+        let mapping = ColumnMapping::parse("date=0,amount=1,balance=2", None, false).unwrap();
+        assert_eq!(mapping.sniff(&excel), 100);
+        let transactions = mapping.parse_sheet(&excel);
+        assert_eq!(transactions[0].date, NaiveDate::from_ymd_opt(2024, 1, 5));
+        assert_eq!(
+            mapping.balances(&excel).opening,
+            Some(Balance {
+                date: NaiveDate::from_ymd_opt(2024, 1, 5),
+                amount: Decimal::from(2000),
+            })
+        );
+    }
+
+    #[test]
+    fn test_transferwise_balances() {
+        // the newest row is the first one, Running Balance is the 7th column
+        let range = csv_range(
+            "ID;Date;Amount;Currency;Description;Reference;Running Balance;From;To;Rate;\
+             Payer;Payee;Account;Merchant;Fees\n\
+             T2;05-01-2024;-20.50;EUR;Card;;79.50;;;;;;;Shop;0\n\
+             T1;05-01-2024;100.00;EUR;Top up;;100.00;;;;;;;;0\n",
+        );
+        let balances = CsvExport(SheetFormat::Transferwise).balances(&range);
+        let date = NaiveDate::from_ymd_opt(2024, 1, 5);
+        assert_eq!(
+            balances.opening,
+            Some(Balance {
+                date,
+                amount: Decimal::ZERO,
+            })
+        );
+        assert_eq!(
+            balances.closing,
+            Some(Balance {
+                date,
+                amount: Decimal::new(7950, 2),
+            })
+        );
+        assert!(CsvExport(SheetFormat::Magnet).balances(&range).is_empty());
+    }
+}
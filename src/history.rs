@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::Local;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::Text;
+
+use crate::external_models::ExternalTransaction;
+use crate::utils::table_exists;
+
+// The statement rows imported by correlate, with the transaction created from them, so the
+// rows are recognized, when an overlapping statement is imported again. GnuCash ignores it.
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS financ_imports (\
+     account_guid text NOT NULL, identity text NOT NULL, tx_guid text NOT NULL, \
+     imported text NOT NULL, PRIMARY KEY (account_guid, identity))";
+
+#[derive(QueryableByName)]
+struct ImportRow {
+    #[diesel(sql_type = Text)]
+    identity: String,
+    #[diesel(sql_type = Text)]
+    tx_guid: String,
+}
+
+// FNV-1a, which is stable between the versions and the platforms, unlike the std hashers
+fn content_hash(transaction: &ExternalTransaction) -> u64 {
+    let content = format!(
+        "{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+        transaction.date,
+        transaction.booking_date,
+        transaction.amount.map(|amount| amount.normalize()),
+        transaction.transaction_fee.map(|fee| fee.normalize()),
+        transaction.description,
+        transaction.category,
+        transaction.other_account,
+        transaction.other_account_name,
+    );
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// The stable identity of every row: the reference of the bank, or the hash of the content,
+// where the repeated rows of the statement, like two coffees on a day, are numbered
+pub fn identities(transactions: &[ExternalTransaction]) -> Vec<String> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    transactions
+        .iter()
+        .map(|transaction| {
+            let identity = match &transaction.reference {
+                Some(reference) => format!("ref:{}", reference),
+                None => format!("hash:{:016x}", content_hash(transaction)),
+            };
+            let count = seen.entry(identity.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => identity,
+                count => format!("{}#{}", identity, count),
+            }
+        })
+        .collect()
+}
+
+// The imported rows of an account, whose transaction is still in the book
+pub struct ImportHistory {
+    transactions: HashMap<String, String>,
+}
+
+impl ImportHistory {
+    pub fn load(connection: &mut SqliteConnection, account_guid: &str) -> Result<Self> {
+        // a dry run should not create the table
+        if !table_exists(connection, "financ_imports")? {
+            return Ok(ImportHistory {
+                transactions: HashMap::new(),
+            });
+        }
+        let rows = sql_query(
+            "SELECT identity, tx_guid FROM financ_imports WHERE account_guid = ? \
+             AND EXISTS (SELECT 1 FROM transactions WHERE guid = financ_imports.tx_guid)",
+        )
+        .bind::<Text, _>(account_guid)
+        .load::<ImportRow>(connection)?;
+        Ok(ImportHistory {
+            transactions: rows
+                .into_iter()
+                .map(|row| (row.identity, row.tx_guid))
+                .collect(),
+        })
+    }
+
+    // The transaction created from the row with the given identity
+    pub fn transaction_of(&self, identity: &str) -> Option<&str> {
+        self.transactions.get(identity).map(String::as_str)
+    }
+}
+
+// Remembers, that the row is imported as the given transaction, an earlier import of the same
+// row is replaced, as its transaction is no longer in the book
+pub fn record_import(
+    connection: &mut SqliteConnection,
+    account_guid: &str,
+    identity: &str,
+    tx_guid: &str,
+) -> Result<()> {
+    sql_query(CREATE_TABLE).execute(connection)?;
+    sql_query(
+        "INSERT OR REPLACE INTO financ_imports (account_guid, identity, tx_guid, imported) \
+         VALUES (?, ?, ?, ?)",
+    )
+    .bind::<Text, _>(account_guid)
+    .bind::<Text, _>(identity)
+    .bind::<Text, _>(tx_guid)
+    .bind::<Text, _>(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+    .execute(connection)?;
+    Ok(())
+}
+
+// Remembers the matched rows with their transaction, all of them, or none
+pub fn record_imports(
+    connection: &mut SqliteConnection,
+    account_guid: &str,
+    imports: &[(String, String)],
+) -> Result<()> {
+    connection.transaction::<_, anyhow::Error, _>(|connection| {
+        for (identity, tx_guid) in imports {
+            record_import(connection, account_guid, identity, tx_guid)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    fn transaction(amount: i64, reference: Option<&str>) -> ExternalTransaction {
+        ExternalTransaction {
+            date: NaiveDate::from_ymd_opt(2024, 1, 5),
+            booking_date: None,
+            amount: Some(Decimal::from(amount)),
+            category: None,
+            description: Some("Coffee".to_owned()),
+            other_account: None,
+            other_account_name: None,
+            textual_date: None,
+            transaction_fee: None,
+            reference: reference.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_identities_and_history() {
+        let rows = [
+            transaction(-500, None),
+            transaction(-500, None),
+            transaction(-700, Some("A1")),
+        ];
+        let identities = identities(&rows);
+        assert!(identities[0].starts_with("hash:"));
+        assert_eq!(identities[1], format!("{}#2", identities[0]));
+        assert_eq!(identities[2], "ref:A1");
+        // the same content is the same identity in an other statement
+        assert_eq!(super::identities(&rows[1..2]), identities[0..1]);
+
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        sql_query("CREATE TABLE transactions (guid text PRIMARY KEY)")
+            .execute(&mut connection)
+            .unwrap();
+        sql_query("INSERT INTO transactions VALUES ('tx1')")
+            .execute(&mut connection)
+            .unwrap();
+        record_imports(
+            &mut connection,
+            "bank",
+            &[
+                (identities[0].clone(), "tx1".to_owned()),
+                (identities[2].clone(), "deleted".to_owned()),
+            ],
+        )
+        .unwrap();
+        let history = ImportHistory::load(&mut connection, "bank").unwrap();
+        assert_eq!(history.transaction_of(&identities[0]), Some("tx1"));
+        // the rows of a deleted transaction can be imported again
+        assert_eq!(history.transaction_of(&identities[2]), None);
+        let other = ImportHistory::load(&mut connection, "other").unwrap();
+        assert_eq!(other.transaction_of(&identities[0]), None);
+    }
+}
@@ -0,0 +1,496 @@
+use std::cell::Cell;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use console::{Term, style};
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Nullable, Text};
+
+use crate::utils::table_exists;
+
+// Every modifying run is recorded in the financ_runs table, and the GUIDs it created or changed
+// in the financ_journal table, so the run can be reverted later. The deleted rows are copied
+// into tables with the same columns as the original ones. GnuCash ignores these tables.
+const CREATE_TABLES: [&str; 4] = [
+    "CREATE TABLE IF NOT EXISTS financ_runs (id INTEGER PRIMARY KEY AUTOINCREMENT, \
+     started text NOT NULL, command text NOT NULL, backup text, undone text)",
+    "CREATE TABLE IF NOT EXISTS financ_journal (run_id integer NOT NULL, \
+     kind text NOT NULL, guid text NOT NULL, previous text, previous_date text)",
+    "CREATE TABLE IF NOT EXISTS financ_deleted_transactions AS \
+     SELECT * FROM transactions WHERE 0",
+    "CREATE TABLE IF NOT EXISTS financ_deleted_splits AS SELECT * FROM splits WHERE 0",
+];
+
+const CREATED_TRANSACTION: &str = "transaction";
+const CREATED_SPLIT: &str = "split";
+// the previous column keeps the previous account
+const MOVED_SPLIT: &str = "move";
+// the previous column keeps the previous state, the previous_date column the previous date
+const RECONCILED_SPLIT: &str = "reconcile";
+const DELETED_TRANSACTION: &str = "delete-transaction";
+const DELETED_SPLIT: &str = "delete-split";
+// the previous column keeps the previous text
+const CHANGED_DESCRIPTION: &str = "description";
+const CHANGED_MEMO: &str = "memo";
+
+#[derive(QueryableByName)]
+struct DatabaseFile {
+    #[diesel(sql_type = Text)]
+    name: String,
+    #[diesel(sql_type = Text)]
+    file: String,
+}
+
+#[derive(QueryableByName)]
+struct RowId {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+#[derive(QueryableByName)]
+struct RunRow {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+    #[diesel(sql_type = Text)]
+    started: String,
+    #[diesel(sql_type = Text)]
+    command: String,
+}
+
+#[derive(QueryableByName)]
+struct JournalRow {
+    #[diesel(sql_type = Text)]
+    kind: String,
+    #[diesel(sql_type = Text)]
+    guid: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    previous: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    previous_date: Option<String>,
+}
+
+// The run starts with the first change, so no backup is saved, when nothing is written
+pub struct Journal {
+    command: String,
+    term: Term,
+    run_id: Cell<Option<i32>>,
+}
+
+// How a modifying command runs: a dry run only prints what it would change, otherwise every
+// change is written into the book, and recorded in the journal
+#[derive(Copy, Clone)]
+pub enum Changes<'a> {
+    DryRun,
+    Write(&'a Journal),
+}
+
+impl Changes<'_> {
+    pub fn is_dry_run(&self) -> bool {
+        matches!(self, Changes::DryRun)
+    }
+}
+
+// Copies the book next to itself, like book.gnucash.20240105123000.gnucash, with VACUUM INTO,
+// which is consistent, even if GnuCash has the book open. In-memory databases are not saved.
+fn backup(connection: &mut SqliteConnection) -> Result<Option<String>> {
+    let databases = sql_query("PRAGMA database_list").load::<DatabaseFile>(connection)?;
+    let Some(main) = databases
+        .into_iter()
+        .find(|database| database.name == "main" && !database.file.is_empty())
+    else {
+        return Ok(None);
+    };
+    let timestamp = Local::now().format("%Y%m%d%H%M%S");
+    // more runs can start in the same second
+    let target = (0..)
+        .map(|idx| match idx {
+            0 => format!("{}.{}.gnucash", main.file, timestamp),
+            _ => format!("{}.{}-{}.gnucash", main.file, timestamp, idx),
+        })
+        .find(|candidate| !Path::new(candidate).exists())
+        .expect("Unused backup file name");
+    sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(&target)
+        .execute(connection)
+        .with_context(|| format!("Unable to save the backup to {}", target))?;
+    Ok(Some(target))
+}
+
+fn create_tables(connection: &mut SqliteConnection) -> Result<()> {
+    for statement in CREATE_TABLES {
+        sql_query(statement).execute(connection)?;
+    }
+    Ok(())
+}
+
+impl Journal {
+    pub fn new(command: &str, term: &Term) -> Self {
+        Journal {
+            command: command.to_owned(),
+            term: term.clone(),
+            run_id: Cell::new(None),
+        }
+    }
+
+    // Saves a backup of the book, and starts the run in the journal, unless it's started already.
+    // Called before the changes, outside of their transaction, as VACUUM INTO can't run in one.
+    pub fn begin(&self, connection: &mut SqliteConnection) -> Result<()> {
+        if self.run_id.get().is_some() {
+            return Ok(());
+        }
+        let backup_file = backup(connection)?;
+        create_tables(connection)?;
+        sql_query("INSERT INTO financ_runs (started, command, backup) VALUES (?, ?, ?)")
+            .bind::<Text, _>(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .bind::<Text, _>(&self.command)
+            .bind::<Nullable<Text>, _>(&backup_file)
+            .execute(connection)?;
+        let run_id = sql_query("SELECT last_insert_rowid() AS id")
+            .get_result::<RowId>(connection)?
+            .id;
+        self.run_id.set(Some(run_id));
+        if let Some(file) = &backup_file {
+            self.term.write_line(&format!(
+                "Run {} of {}, backup saved to {}",
+                style(run_id).cyan(),
+                self.command,
+                style(file).blue()
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        connection: &mut SqliteConnection,
+        kind: &str,
+        guid: &str,
+        previous: Option<&str>,
+        previous_date: Option<&str>,
+    ) -> Result<()> {
+        let run_id = self
+            .run_id
+            .get()
+            .ok_or_else(|| anyhow!("The run of {} is not started!", self.command))?;
+        sql_query(
+            "INSERT INTO financ_journal (run_id, kind, guid, previous, previous_date) \
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind::<Integer, _>(run_id)
+        .bind::<Text, _>(kind)
+        .bind::<Text, _>(guid)
+        .bind::<Nullable<Text>, _>(previous)
+        .bind::<Nullable<Text>, _>(previous_date)
+        .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn created_transaction(&self, connection: &mut SqliteConnection, guid: &str) -> Result<()> {
+        self.record(connection, CREATED_TRANSACTION, guid, None, None)
+    }
+
+    pub fn created_split(&self, connection: &mut SqliteConnection, guid: &str) -> Result<()> {
+        self.record(connection, CREATED_SPLIT, guid, None, None)
+    }
+
+    pub fn moved_split(
+        &self,
+        connection: &mut SqliteConnection,
+        guid: &str,
+        previous_account_guid: &str,
+    ) -> Result<()> {
+        self.record(
+            connection,
+            MOVED_SPLIT,
+            guid,
+            Some(previous_account_guid),
+            None,
+        )
+    }
+
+    pub fn reconciled_split(
+        &self,
+        connection: &mut SqliteConnection,
+        guid: &str,
+        previous_state: &str,
+        previous_date: Option<&str>,
+    ) -> Result<()> {
+        self.record(
+            connection,
+            RECONCILED_SPLIT,
+            guid,
+            Some(previous_state),
+            previous_date,
+        )
+    }
+
+    // Called before the transaction is deleted, as its row is copied
+    pub fn deleted_transaction(&self, connection: &mut SqliteConnection, guid: &str) -> Result<()> {
+        sql_query(
+            "INSERT INTO financ_deleted_transactions SELECT * FROM transactions WHERE guid = ?",
+        )
+        .bind::<Text, _>(guid)
+        .execute(connection)?;
+        self.record(connection, DELETED_TRANSACTION, guid, None, None)
+    }
+
+    // Called before the split is deleted, as its row is copied
+    pub fn deleted_split(&self, connection: &mut SqliteConnection, guid: &str) -> Result<()> {
+        sql_query("INSERT INTO financ_deleted_splits SELECT * FROM splits WHERE guid = ?")
+            .bind::<Text, _>(guid)
+            .execute(connection)?;
+        self.record(connection, DELETED_SPLIT, guid, None, None)
+    }
+
+    pub fn changed_description(
+        &self,
+        connection: &mut SqliteConnection,
+        guid: &str,
+        previous_description: &str,
+    ) -> Result<()> {
+        self.record(
+            connection,
+            CHANGED_DESCRIPTION,
+            guid,
+            Some(previous_description),
+            None,
+        )
+    }
+
+    pub fn changed_memo(
+        &self,
+        connection: &mut SqliteConnection,
+        guid: &str,
+        previous_memo: &str,
+    ) -> Result<()> {
+        self.record(connection, CHANGED_MEMO, guid, Some(previous_memo), None)
+    }
+}
+
+// Copies the deleted row back from the given copy of the table, and drops the copy of it
+fn restore(
+    connection: &mut SqliteConnection,
+    table: &str,
+    copy_table: &str,
+    guid: &str,
+) -> Result<usize> {
+    let restored = sql_query(format!(
+        "INSERT INTO {} SELECT * FROM {} WHERE guid = ?",
+        table, copy_table
+    ))
+    .bind::<Text, _>(guid)
+    .execute(connection)?;
+    sql_query(format!("DELETE FROM {} WHERE guid = ?", copy_table))
+        .bind::<Text, _>(guid)
+        .execute(connection)?;
+    Ok(restored)
+}
+
+// Reverts the given run, or the last one, which changed anything and is not undone yet:
+// the created splits and transactions are deleted, the moved splits are moved back,
+// the deleted ones are restored. A dry run only lists the changes to revert.
+pub fn undo(
+    connection: &mut SqliteConnection,
+    run: Option<i32>,
+    dry_run: bool,
+    term: &Term,
+) -> Result<usize> {
+    if !table_exists(connection, "financ_journal")? {
+        return Err(anyhow!("Nothing to undo!"));
+    }
+    if !dry_run {
+        create_tables(connection)?;
+    }
+    let runs = sql_query(
+        "SELECT id, started, command FROM financ_runs WHERE undone IS NULL \
+         AND EXISTS (SELECT 1 FROM financ_journal WHERE run_id = financ_runs.id) \
+         AND (? IS NULL OR id = ?) ORDER BY id DESC",
+    )
+    .bind::<Nullable<Integer>, _>(run)
+    .bind::<Nullable<Integer>, _>(run)
+    .load::<RunRow>(connection)?;
+    let Some(selected) = runs.first() else {
+        return Err(match run {
+            Some(id) => anyhow!("Run {} not found, or it's already undone!", id),
+            None => anyhow!("Nothing to undo!"),
+        });
+    };
+    term.write_line(&format!(
+        "{} run {} of {} from {}",
+        if dry_run {
+            "Dry run, would undo"
+        } else {
+            "Undoing"
+        },
+        style(selected.id).cyan(),
+        selected.command,
+        selected.started
+    ))?;
+    let entries = sql_query(
+        "SELECT kind, guid, previous, previous_date FROM financ_journal \
+         WHERE run_id = ? ORDER BY rowid DESC",
+    )
+    .bind::<Integer, _>(selected.id)
+    .load::<JournalRow>(connection)?;
+    if dry_run {
+        for entry in &entries {
+            term.write_line(&format!(" - {} {}", entry.kind, entry.guid))?;
+        }
+        return Ok(entries.len());
+    }
+
+    connection.transaction::<_, anyhow::Error, _>(|connection| {
+        use crate::schema::{splits, transactions};
+        for entry in &entries {
+            let changed = match (entry.kind.as_str(), &entry.previous) {
+                (CREATED_SPLIT, _) => {
+                    diesel::delete(splits::table.find(&entry.guid)).execute(connection)?
+                }
+                (CREATED_TRANSACTION, _) => {
+                    diesel::delete(transactions::table.find(&entry.guid)).execute(connection)?
+                }
+                (MOVED_SPLIT, Some(previous)) => diesel::update(splits::table.find(&entry.guid))
+                    .set(splits::account_guid.eq(previous))
+                    .execute(connection)?,
+                (RECONCILED_SPLIT, Some(previous)) => {
+                    diesel::update(splits::table.find(&entry.guid))
+                        .set((
+                            splits::reconcile_state.eq(previous),
+                            splits::reconcile_date.eq(&entry.previous_date),
+                        ))
+                        .execute(connection)?
+                }
+                (DELETED_TRANSACTION, _) => restore(
+                    connection,
+                    "transactions",
+                    "financ_deleted_transactions",
+                    &entry.guid,
+                )?,
+                (DELETED_SPLIT, _) => {
+                    restore(connection, "splits", "financ_deleted_splits", &entry.guid)?
+                }
+                (CHANGED_DESCRIPTION, Some(previous)) => {
+                    diesel::update(transactions::table.find(&entry.guid))
+                        .set(transactions::description.eq(previous))
+                        .execute(connection)?
+                }
+                (CHANGED_MEMO, Some(previous)) => diesel::update(splits::table.find(&entry.guid))
+                    .set(splits::memo.eq(previous))
+                    .execute(connection)?,
+                _ => return Err(anyhow!("Unknown journal entry: {}", entry.kind)),
+            };
+            if changed == 0 {
+                term.write_line(&format!(
+                    "The {} {} no longer exists, skipping",
+                    entry.kind,
+                    style(&entry.guid).red()
+                ))?;
+            }
+        }
+        sql_query("UPDATE financ_runs SET undone = ? WHERE id = ?")
+            .bind::<Text, _>(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .bind::<Integer, _>(selected.id)
+            .execute(connection)?;
+        Ok(())
+    })?;
+    term.write_line(&format!("Reverted {} changes", style(entries.len()).cyan()))?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn test_undo_reverts_the_last_run() {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        for statement in [
+            "CREATE TABLE transactions (guid text PRIMARY KEY, currency_guid text, num text, \
+             post_date text, enter_date text, description text)",
+            "CREATE TABLE splits (guid text PRIMARY KEY, tx_guid text, account_guid text)",
+            "INSERT INTO transactions (guid) VALUES ('tx1')",
+            "INSERT INTO splits VALUES ('s1', 'tx1', 'food'), ('s2', 'tx1', 'bank')",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+        let term = Term::stderr();
+        let journal = Journal::new("test", &term);
+        journal.begin(&mut connection).unwrap();
+        sql_query("UPDATE splits SET account_guid = 'misc' WHERE guid = 's1'")
+            .execute(&mut connection)
+            .unwrap();
+        journal.moved_split(&mut connection, "s1", "food").unwrap();
+        sql_query("INSERT INTO splits VALUES ('s3', 'tx1', 'fees')")
+            .execute(&mut connection)
+            .unwrap();
+        journal.created_split(&mut connection, "s3").unwrap();
+
+        // the dry run changes nothing
+        assert_eq!(undo(&mut connection, None, true, &term).unwrap(), 2);
+        assert_eq!(undo(&mut connection, None, false, &term).unwrap(), 2);
+        let accounts: Vec<String> = {
+            use crate::schema::splits;
+            splits::table
+                .select(splits::account_guid)
+                .order(splits::guid)
+                .load(&mut connection)
+                .unwrap()
+        };
+        assert_eq!(accounts, vec!["food", "bank"]);
+        assert!(undo(&mut connection, None, false, &term).is_err());
+    }
+
+    #[test]
+    fn test_backup_is_saved_on_the_first_change() {
+        let file = env::temp_dir().join(format!("financ-journal-{}.gnucash", process::id()));
+        let mut connection = SqliteConnection::establish(file.to_str().unwrap()).unwrap();
+        sql_query("CREATE TABLE transactions (guid text PRIMARY KEY)")
+            .execute(&mut connection)
+            .unwrap();
+        sql_query("CREATE TABLE splits (guid text PRIMARY KEY)")
+            .execute(&mut connection)
+            .unwrap();
+        // the backups are saved next to the book, like financ-journal-1.gnucash.20240105.gnucash
+        let book_name = file.file_name().unwrap().to_string_lossy().into_owned();
+        let backups = || -> Vec<_> {
+            fs::read_dir(env::temp_dir())
+                .unwrap()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|backup| {
+                    let name = backup.file_name().unwrap().to_string_lossy();
+                    name.starts_with(&book_name) && name != book_name
+                })
+                .collect()
+        };
+        let term = Term::stderr();
+        let journal = Journal::new("test", &term);
+        let no_backup = backups();
+        journal.begin(&mut connection).unwrap();
+        journal.begin(&mut connection).unwrap();
+        journal.created_split(&mut connection, "s1").unwrap();
+        let saved = backups();
+        let runs = sql_query("SELECT id, started, command FROM financ_runs")
+            .load::<RunRow>(&mut connection)
+            .unwrap();
+        for backup in &saved {
+            fs::remove_file(backup).unwrap();
+        }
+        fs::remove_file(&file).unwrap();
+        assert!(no_backup.is_empty());
+        assert_eq!(saved.len(), 1);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(journal.run_id.get(), Some(runs[0].id));
+    }
+
+    #[test]
+    fn test_change_requires_a_started_run() {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        let journal = Journal::new("test", &Term::stderr());
+        assert!(journal.created_split(&mut connection, "s1").is_err());
+    }
+}
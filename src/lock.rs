@@ -0,0 +1,196 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+
+use anyhow::{Context, Result};
+use console::{Term, style};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Nullable, Text};
+
+// GnuCash keeps a row in the gnclock table while the book is open, it's not in schema.rs,
+// as diesel can't handle a table without a primary key
+#[derive(QueryableByName, Debug)]
+struct LockRow {
+    #[diesel(sql_type = Nullable<Text>, column_name = "Hostname")]
+    hostname: Option<String>,
+    #[diesel(sql_type = Nullable<Integer>, column_name = "PID")]
+    pid: Option<i32>,
+}
+
+struct BookLock {
+    hostname: String,
+    pid: i32,
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_owned())
+}
+
+impl BookLock {
+    // The lock is checked and taken in one write transaction, so no one else takes it between
+    fn acquire(connection: &mut SqliteConnection, force: bool, term: &Term) -> Result<Self> {
+        connection.immediate_transaction(|connection| BookLock::insert(connection, force, term))
+    }
+
+    fn insert(connection: &mut SqliteConnection, force: bool, term: &Term) -> Result<Self> {
+        let existing = sql_query("SELECT Hostname, PID FROM gnclock")
+            .load::<LockRow>(connection)
+            .context("Unable to read the gnclock table")?;
+        if let Some(row) = existing.first() {
+            let owner = format!(
+                "{}:{}",
+                row.hostname.as_deref().unwrap_or_default(),
+                row.pid.unwrap_or_default()
+            );
+            if !force {
+                term.write_line(&format!(
+                    "The book is locked by {}, close GnuCash, or use --force to write anyway!",
+                    style(&owner).red()
+                ))?;
+                return Err(anyhow!("The book is locked by {}!", owner));
+            }
+            term.write_line(&format!(
+                "Ignoring the lock of {}, as --force is given",
+                style(&owner).red()
+            ))?;
+        }
+        let lock = BookLock {
+            hostname: hostname(),
+            pid: process::id() as i32,
+        };
+        sql_query("INSERT INTO gnclock (Hostname, PID) VALUES (?, ?)")
+            .bind::<Text, _>(&lock.hostname)
+            .bind::<Integer, _>(lock.pid)
+            .execute(connection)
+            .context("Unable to lock the book")?;
+        Ok(lock)
+    }
+
+    fn release(&self, connection: &mut SqliteConnection) -> Result<()> {
+        sql_query("DELETE FROM gnclock WHERE Hostname = ? AND PID = ?")
+            .bind::<Text, _>(&self.hostname)
+            .bind::<Integer, _>(self.pid)
+            .execute(connection)
+            .context("Unable to remove the lock of the book")?;
+        Ok(())
+    }
+}
+
+// Removes the lock, when it is dropped without releasing it, like when the modification panics
+struct LockGuard<'c> {
+    connection: &'c mut SqliteConnection,
+    lock: Option<BookLock>,
+}
+
+impl LockGuard<'_> {
+    fn release(&mut self) -> Result<()> {
+        match self.lock.take() {
+            Some(lock) => lock.release(self.connection),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // a panic leaves the transaction of the modification open, the lock would be
+            // removed in it, and rolled back with it
+            let _ = self.connection.batch_execute("ROLLBACK");
+        }
+        let _ = self.release();
+    }
+}
+
+// Runs the modification while holding the lock of the book, the lock is removed even if it fails
+pub fn with_book_lock<T>(
+    connection: &mut SqliteConnection,
+    force: bool,
+    term: &Term,
+    modification: impl FnOnce(&mut SqliteConnection) -> Result<T>,
+) -> Result<T> {
+    let lock = BookLock::acquire(connection, force, term)?;
+    let mut guard = LockGuard {
+        connection,
+        lock: Some(lock),
+    };
+    let result = modification(guard.connection);
+    let released = guard.release();
+    let value = result?;
+    released?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection_with_lock_table() -> SqliteConnection {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        sql_query("CREATE TABLE gnclock ( Hostname varchar(255), PID int )")
+            .execute(&mut connection)
+            .unwrap();
+        connection
+    }
+
+    fn count_locks(connection: &mut SqliteConnection) -> usize {
+        sql_query("SELECT Hostname, PID FROM gnclock")
+            .load::<LockRow>(connection)
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn test_lock_is_taken_and_released() {
+        let mut connection = connection_with_lock_table();
+        let term = Term::stderr();
+        let locks_inside =
+            with_book_lock(&mut connection, false, &term, |c| Ok(count_locks(c))).unwrap();
+        assert_eq!(locks_inside, 1);
+        assert_eq!(count_locks(&mut connection), 0);
+    }
+
+    #[test]
+    fn test_lock_is_released_on_panic() {
+        let file = env::temp_dir().join(format!("financ-lock-{}.gnucash", process::id()));
+        let path = file.to_str().unwrap();
+        let mut connection = SqliteConnection::establish(path).unwrap();
+        sql_query("CREATE TABLE gnclock ( Hostname varchar(255), PID int )")
+            .execute(&mut connection)
+            .unwrap();
+        let term = Term::stderr();
+        // the panic leaves the transaction open in the middle
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            with_book_lock(&mut connection, false, &term, |c| {
+                c.transaction::<(), anyhow::Error, _>(|_| panic!("failed"))
+            })
+        }));
+        assert!(panicked.is_err());
+        // an other connection, like GnuCash, sees the lock removed
+        let mut other = SqliteConnection::establish(path).unwrap();
+        let locks = count_locks(&mut other);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(locks, 0);
+    }
+
+    #[test]
+    fn test_locked_book_requires_force() {
+        let mut connection = connection_with_lock_table();
+        sql_query("INSERT INTO gnclock (Hostname, PID) VALUES ('desktop', 42)")
+            .execute(&mut connection)
+            .unwrap();
+        let term = Term::stderr();
+        assert!(with_book_lock(&mut connection, false, &term, |_| Ok(())).is_err());
+        assert!(with_book_lock(&mut connection, true, &term, |_| Ok(())).is_ok());
+        // the lock of GnuCash is kept
+        assert_eq!(count_locks(&mut connection), 1);
+    }
+}
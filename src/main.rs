@@ -16,15 +16,34 @@ extern crate anyhow;
 #[macro_use]
 extern crate lazy_static;
 
+mod acceptance;
+mod assignment;
+mod balance;
 mod cli;
 pub mod correlator;
+mod counter_rules;
 mod dbmodifier;
+mod detection;
+mod duplicates;
+mod export;
 mod external_models;
+mod format_file;
 mod formats;
+mod history;
+mod journal;
+mod lock;
 pub mod models;
 mod query;
+mod readers;
+mod reconcile;
+mod report;
+mod review;
 pub mod schema;
 mod sheets;
+mod similarity;
+mod suggestion;
+#[cfg(test)]
+mod test_fixtures;
 pub mod utils;
 
 use std::io;
@@ -32,17 +51,35 @@ use std::io;
 use anyhow::{Context, Result};
 use clap::{CommandFactory, Parser};
 use clap_complete::{Shell, generate};
-use cli::{Commands, CommoditiesArgs, CorrelateArgs, ListAccountsArgs, TransactionsArgs};
+use cli::{
+    Commands, CommoditiesArgs, CorrelateArgs, DuplicatesArgs, ExportArgs, ListAccountsArgs,
+    TransactionsArgs, UndoArgs,
+};
 use console::{Term, style};
 
+use crate::acceptance::{AcceptRule, Acceptance};
 use crate::cli::Cli;
 use crate::correlator::CorrelationCommand;
-use crate::external_models::Matching;
-use crate::formats::SheetFormat;
+use crate::detection::{choose_format, detect_formats};
+use crate::duplicates::DuplicatesCommand;
+use crate::export::ExportCommand;
+use crate::external_models::{
+    AmountTolerance, Matching, MatchingSettings, SheetParser, StatementFormat,
+};
+use crate::format_file::{FormatFile, find_format_file};
+use crate::formats::{ColumnMapping, CsvExport, SheetFormat};
+use crate::journal::{Changes, Journal, undo};
+use crate::lock::with_book_lock;
 use crate::query::accounts::ToAccountQuery;
 use crate::query::currencies::CommoditiesQuery;
 use crate::query::transactions::TransactionQuery;
-use crate::utils::establish_connection;
+use crate::readers::camt::CamtFormat;
+use crate::readers::csv::CsvOptions;
+use crate::readers::mt940::Mt940Format;
+use crate::readers::ofx::OfxFormat;
+use crate::readers::qif::QifFormat;
+use crate::report::ReportFormat;
+use crate::utils::{establish_connection, to_date};
 
 fn main() {
     let cli = Cli::parse();
@@ -52,11 +89,25 @@ fn main() {
         Commands::Transactions(args) => handle_list_entries(args),
         Commands::Commodities(args) => handle_commodities(args),
         Commands::Correlate(args) => handle_correlate(args),
+        Commands::Export(args) => handle_export(args),
+        Commands::Duplicates(args) => handle_duplicates(args),
+        Commands::Undo(args) => handle_undo(args),
         Commands::Completions { shell } => handle_shell_completions(shell),
     }
     .unwrap();
 }
 
+fn handle_undo(args: UndoArgs) -> Result<usize> {
+    let term = Term::stdout();
+    let mut connection = establish_connection();
+    if args.dry_run {
+        return undo(&mut connection, args.run, true, &term);
+    }
+    with_book_lock(&mut connection, args.force, &term, |connection| {
+        undo(connection, args.run, false, &term)
+    })
+}
+
 fn handle_shell_completions(shell: Shell) -> Result<usize> {
     let mut cmd = Cli::command();
     eprintln!("Generating completion file for {shell:?}...");
@@ -75,6 +126,8 @@ fn handle_list_entries(args: TransactionsArgs) -> Result<usize> {
     let term = Term::stdout();
 
     let mut connection = establish_connection();
+    let force = args.force;
+    let dry_run = args.dry_run;
     let account_query = args.account.build(None);
     let move_target_account = if args.move_split {
         let target_account_query = args.target_account.build(None);
@@ -119,7 +172,24 @@ fn handle_list_entries(args: TransactionsArgs) -> Result<usize> {
         TransactionQuery::from(args)
     };
     // term.write_line(&format!("Limit is {}", style(q.limit).red()))?;
-    q.execute_and_process(&mut connection, &move_target_account, &term)
+    if move_target_account.is_some() && !dry_run {
+        with_book_lock(&mut connection, force, &term, |connection| {
+            let journal = Journal::new("move-split", &term);
+            q.execute_and_process(
+                connection,
+                &move_target_account,
+                Changes::Write(&journal),
+                &term,
+            )
+        })
+    } else {
+        q.execute_and_process(
+            &mut connection,
+            &move_target_account,
+            Changes::DryRun,
+            &term,
+        )
+    }
 }
 
 fn handle_commodities(cmd: CommoditiesArgs) -> Result<usize> {
@@ -128,30 +198,172 @@ fn handle_commodities(cmd: CommoditiesArgs) -> Result<usize> {
     q.execute_and_display(&mut connection)
 }
 
-fn handle_correlate(cmd: CorrelateArgs) -> Result<usize> {
-    let requested_format = cmd.format;
+fn handle_export(args: ExportArgs) -> Result<usize> {
+    if !args.format.eq_ignore_ascii_case("qif") {
+        return Err(anyhow!("Unknown export format:'{}'!", args.format));
+    }
+    let mut connection = establish_connection();
+    let cmd = ExportCommand {
+        account_query: args.account.build(None),
+        before: to_date(args.before),
+        after: to_date(args.after),
+        output: args.output,
+    };
+    cmd.execute(&mut connection, &Term::stderr())
+}
 
+fn handle_duplicates(args: DuplicatesArgs) -> Result<usize> {
+    let term = Term::stdout();
     let mut connection = establish_connection();
-    let matching = if cmd.by_booking_date {
-        Matching::ByBooking
+    let account_query = args.account.build(None);
+    let account = if account_query.has_filter() {
+        Some(
+            account_query
+                .get_one(&mut connection, true)
+                .ok_or_else(|| anyhow!("Account is not specified exactly!"))?,
+        )
     } else {
-        Matching::BySpending
+        None
     };
+    let cmd = DuplicatesCommand {
+        account,
+        max_days: args.max_days,
+        min_similarity: f64::from(args.min_similarity) / 100.0,
+        auto: args.auto,
+        force: args.force,
+        dry_run: args.dry_run,
+    };
+    cmd.execute(&mut connection, &term)
+}
 
+fn handle_correlate(cmd: CorrelateArgs) -> Result<usize> {
     let term = Term::stdout();
+    let (format, csv_options) = match &cmd.format {
+        Some(_) => resolve_format(&cmd)?,
+        None => choose_format(
+            detect_formats(&cmd.input, cmd.sheet_name.clone(), &cmd.csv)?,
+            &term,
+        )?,
+    };
+
+    let mut connection = establish_connection();
+    // the command line takes precedence over the settings of the format
+    let format_matching = format.matching();
+    let matching = match (cmd.match_date, cmd.by_booking_date, format_matching.date) {
+        (Some(field), _, _) => Matching::Field(field),
+        (None, true, _) => Matching::ByBooking,
+        (None, false, Some(field)) => Matching::Field(field),
+        (None, false, None) => Matching::BySpending,
+    };
+    let window = MatchingSettings {
+        date: cmd.match_date,
+        max_days: cmd.max_days,
+        days_before: cmd.days_before,
+        days_after: cmd.days_after,
+    }
+    .window(&format_matching);
+
+    if let Some(file) = &cmd.report {
+        ReportFormat::from_path(file)?;
+    }
+    let pending_file = cmd
+        .pending
+        .clone()
+        .unwrap_or_else(|| format!("{}.pending.csv", cmd.input));
     let mut cmd = CorrelationCommand {
         input_file: cmd.input,
         sheet_name: cmd.sheet_name,
+        csv_options,
         matching,
+        window,
+        tolerance: AmountTolerance {
+            absolute: cmd.amount_tolerance.unwrap_or_default(),
+            percent: cmd.amount_tolerance_percent.unwrap_or_default(),
+        },
+        statement_currency: cmd.statement_currency,
+        reconcile: cmd.reconcile,
+        closing_balance: cmd.closing_balance,
         verbose: cmd.verbose,
         list_extra_transactions: cmd.list_extra_transactions,
+        force: cmd.force,
+        dry_run: cmd.dry_run,
+        acceptance: Acceptance {
+            rules: cmd
+                .accept_if
+                .iter()
+                .map(|rule| AcceptRule::parse(rule))
+                .collect::<Result<Vec<_>>>()?,
+            accept_all: cmd.yes,
+            no_input: cmd.yes || cmd.no_input,
+            accept_suggested: cmd.accept_suggested,
+        },
+        pending_file,
+        report_file: cmd.report,
+        review: cmd.review,
+        rules_file: cmd.rules,
         account_query: cmd.account.build(None),
         counterparty_account_query: cmd.from_account.build(None),
         fee_account_query: cmd.fee_account.build(None),
     };
-    let format = requested_format
-        .clone()
-        .and_then(|x| SheetFormat::new(&x))
-        .with_context(|| format!("Unknown format:'{}'!", requested_format.unwrap_or_default()))?;
     cmd.execute(&mut connection, &term, &format)
 }
+
+// Looks up the built-in sheet formats, the generic csv format, and the user defined format files
+fn resolve_format(cmd: &CorrelateArgs) -> Result<(StatementFormat, Option<CsvOptions>)> {
+    let csv = &cmd.csv;
+    let csv_options_from_args = || {
+        CsvOptions::new(
+            csv.delimiter,
+            csv.quote,
+            csv.skip_rows,
+            csv.encoding.as_deref(),
+        )
+    };
+    let name = cmd.format.as_deref().unwrap_or_default();
+    if name.eq_ignore_ascii_case("csv") {
+        let columns = csv
+            .columns
+            .as_ref()
+            .context("The csv format requires a --columns mapping!")?;
+        let mapping = ColumnMapping::parse(columns, csv.date_format.clone(), csv.decimal_comma)?;
+        return Ok((
+            StatementFormat::Sheet(Box::new(mapping)),
+            Some(csv_options_from_args()?),
+        ));
+    }
+    match name.to_lowercase().as_str() {
+        "ofx" | "qfx" => return Ok((StatementFormat::Statement(Box::new(OfxFormat)), None)),
+        "camt" | "camt053" | "camt054" | "camt.053" | "camt.054" => {
+            return Ok((StatementFormat::Statement(Box::new(CamtFormat)), None));
+        }
+        "mt940" => return Ok((StatementFormat::Statement(Box::new(Mt940Format)), None)),
+        "qif" => return Ok((StatementFormat::Statement(Box::new(QifFormat)), None)),
+        _ => {}
+    }
+    let csv_options = if cmd.input.to_lowercase().ends_with(".csv") {
+        Some(csv_options_from_args()?)
+    } else {
+        None
+    };
+    if let Some(format) = SheetFormat::new(name) {
+        let parser: Box<dyn SheetParser> = match csv_options {
+            Some(_) => Box::new(CsvExport(format)),
+            None => Box::new(format),
+        };
+        return Ok((StatementFormat::Sheet(parser), csv_options));
+    }
+    if let Some(path) = find_format_file(name) {
+        let format_file = FormatFile::load(&path)?;
+        let file_csv_options = format_file.csv_options(
+            csv.delimiter,
+            csv.quote,
+            csv.skip_rows,
+            csv.encoding.as_deref(),
+        )?;
+        return Ok((
+            StatementFormat::Sheet(Box::new(format_file.to_mapping()?)),
+            file_csv_options.or(csv_options),
+        ));
+    }
+    Err(anyhow!("Unknown format:'{}'!", name))
+}
@@ -4,13 +4,13 @@ use chrono::NaiveDateTime;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::FromPrimitive;
 
-use crate::schema::{accounts, splits, transactions};
+use crate::schema::{accounts, prices, splits, transactions};
 use crate::utils::{get_value_or_empty, parse_sqlite_date};
 
 joinable!(splits -> transactions (tx_guid));
 joinable!(splits -> accounts (account_guid));
 
-#[derive(Queryable)]
+#[derive(Queryable, Clone)]
 pub struct Account {
     pub guid: String,
     pub name: String,
@@ -55,6 +55,19 @@ pub struct Transaction {
     pub description: Option<String>,
 }
 
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = prices)]
+pub struct Price {
+    pub guid: String,
+    pub commodity_guid: String,
+    pub currency_guid: String,
+    pub date: String,
+    pub source: Option<String>,
+    pub type_: Option<String>,
+    pub value_num: i64,
+    pub value_denom: i64,
+}
+
 #[derive(Queryable, Debug)]
 pub struct Commodities {
     pub guid: String,
@@ -125,6 +138,16 @@ impl fmt::Display for Split {
     }
 }
 
+impl Price {
+    pub fn date(&self) -> Option<NaiveDateTime> {
+        parse_sqlite_date(&Some(self.date.clone()))
+    }
+
+    pub fn get_value_as_decimal(&self) -> Decimal {
+        Split::as_decimal(self.value_num, self.value_denom)
+    }
+}
+
 impl Transaction {
     pub fn posting(&self) -> Option<NaiveDateTime> {
         parse_sqlite_date(&self.post_date)
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt;
 
 use diesel::prelude::*;
@@ -133,6 +134,21 @@ impl AccountQuery {
         }
     }
 
+    // Whether any filter is given, or every account is selected
+    pub fn has_filter(&self) -> bool {
+        [
+            &self.guid_filter,
+            &self.name_filter,
+            &self.parent_filter,
+            &self.type_filter,
+            &self.parent_name_filter,
+            &self.commodity_id_filter,
+            &self.commodity_name_filter,
+        ]
+        .iter()
+        .any(|filter| filter.is_some())
+    }
+
     pub fn get_one(
         &self,
         connection: &mut SqliteConnection,
@@ -155,6 +171,53 @@ impl AccountQuery {
     }
 }
 
+// The full name of every account, like Expenses:Groceries, without the root account
+pub fn accounts_with_full_names(connection: &mut SqliteConnection) -> Vec<(String, Account)> {
+    use crate::schema::accounts;
+
+    let all = accounts::table
+        .load::<Account>(connection)
+        .expect("Error loading accounts");
+    let by_guid: HashMap<&str, &Account> = all
+        .iter()
+        .map(|account| (account.guid.as_str(), account))
+        .collect();
+    let full_name = |account: &Account| {
+        let mut names = vec![account.name.as_str()];
+        let mut parent = account.parent_guid.as_deref();
+        while let Some(current) = parent.and_then(|guid| by_guid.get(guid)) {
+            if current.parent_guid.is_none() {
+                // the root account is not part of the name
+                break;
+            }
+            names.push(current.name.as_str());
+            parent = current.parent_guid.as_deref();
+        }
+        names.reverse();
+        names.join(":")
+    };
+    let names: Vec<Option<String>> = all
+        .iter()
+        .map(|account| account.parent_guid.is_some().then(|| full_name(account)))
+        .collect();
+    names
+        .into_iter()
+        .zip(all)
+        .filter_map(|(name, account)| Some((name?, account)))
+        .collect()
+}
+
+// The account by its full name or GUID, from the accounts_with_full_names
+pub fn find_by_path_or_guid<'a>(
+    accounts: &'a [(String, Account)],
+    reference: &str,
+) -> Option<&'a Account> {
+    accounts
+        .iter()
+        .find(|(full_name, account)| full_name == reference || account.guid == reference)
+        .map(|(_, account)| account)
+}
+
 impl fmt::Display for AccountQuery {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -56,6 +56,17 @@ impl CommoditiesQuery {
             .expect("Error loading a commodity")
             .pop()
     }
+
+    pub fn get_by_mnemonic(connection: &mut SqliteConnection, name: &str) -> Option<Commodities> {
+        use crate::schema::commodities::dsl::*;
+
+        commodities
+            .filter(mnemonic.eq(name.to_uppercase()))
+            .limit(1)
+            .load::<Commodities>(connection)
+            .expect("Error loading a commodity")
+            .pop()
+    }
 }
 
 impl From<CommoditiesArgs> for CommoditiesQuery {
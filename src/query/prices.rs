@@ -0,0 +1,91 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::models::Price;
+
+// The exchange rates from one commodity to an other from the prices table, ordered by date,
+// the prices quoted the other way around are inverted
+pub struct PriceList {
+    rates: Vec<(NaiveDate, Decimal)>,
+}
+
+impl PriceList {
+    pub fn load(connection: &mut SqliteConnection, from_guid: &str, to_guid: &str) -> Result<Self> {
+        use crate::schema::prices;
+
+        let loaded = prices::table
+            .filter(
+                (prices::commodity_guid
+                    .eq(from_guid)
+                    .and(prices::currency_guid.eq(to_guid)))
+                .or(prices::commodity_guid
+                    .eq(to_guid)
+                    .and(prices::currency_guid.eq(from_guid))),
+            )
+            .select(Price::as_select())
+            .load::<Price>(connection)?;
+        let mut rates: Vec<(NaiveDate, Decimal)> = loaded
+            .iter()
+            .filter(|price| price.value_num != 0 && price.value_denom != 0)
+            .filter_map(|price| {
+                let value = price.get_value_as_decimal();
+                let rate = if price.commodity_guid == from_guid {
+                    value
+                } else {
+                    Decimal::ONE / value
+                };
+                Some((price.date()?.date(), rate))
+            })
+            .collect();
+        rates.sort_by_key(|(date, _)| *date);
+        Ok(PriceList { rates })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    // The last rate on or before the date, or the first one, if every rate is later
+    pub fn rate_at(&self, date: NaiveDate) -> Option<Decimal> {
+        let later = self
+            .rates
+            .partition_point(|(rate_date, _)| *rate_date <= date);
+        match later {
+            0 => self.rates.first(),
+            _ => self.rates.get(later - 1),
+        }
+        .map(|(_, rate)| *rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::sql_query;
+
+    #[test]
+    fn test_rates_are_inverted_and_picked_by_date() {
+        let mut connection = SqliteConnection::establish(":memory:").unwrap();
+        for statement in [
+            "CREATE TABLE prices (guid text(32) PRIMARY KEY NOT NULL, \
+             commodity_guid text(32) NOT NULL, currency_guid text(32) NOT NULL, \
+             date text(19) NOT NULL, source text(2048), type text(2048), \
+             value_num bigint NOT NULL, value_denom bigint NOT NULL)",
+            "INSERT INTO prices VALUES ('p1', 'eur', 'huf', '2024-01-01 10:00:00', NULL, NULL, \
+             400, 1), ('p2', 'huf', 'eur', '2024-02-01 10:00:00', NULL, NULL, 1, 500)",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+        let prices = PriceList::load(&mut connection, "eur", "huf").unwrap();
+        let date = |month| NaiveDate::from_ymd_opt(2024, month, 15).unwrap();
+        assert_eq!(prices.rate_at(date(1)), Some(Decimal::from(400)));
+        assert_eq!(prices.rate_at(date(3)), Some(Decimal::from(500)));
+        // before the first price, the first one is used
+        assert_eq!(
+            prices.rate_at(NaiveDate::from_ymd_opt(2023, 12, 1).unwrap()),
+            Some(Decimal::from(400))
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use chrono::naive::NaiveDate;
 use console::{Term, style};
 use diesel::prelude::*;
 
 use crate::cli::TransactionsArgs;
+use crate::journal::Changes;
 use crate::models::{Account, Split, Transaction};
 use crate::utils::{format_sqlite_date, to_date};
 
@@ -70,12 +73,13 @@ impl TransactionQuery {
         &self,
         connection: &mut SqliteConnection,
         target_account: &Option<Account>,
+        changes: Changes,
         term: &Term,
     ) -> Result<usize> {
         let results = self.execute(connection);
         match target_account {
             None => self.display(results),
-            Some(account) => self.move_splits(connection, results, account, term),
+            Some(account) => self.move_splits(connection, results, account, changes, term),
         }
     }
 
@@ -91,34 +95,87 @@ impl TransactionQuery {
         Ok(len)
     }
 
+    // On a dry run, the splits are only listed
     fn move_splits(
         &self,
         connection: &mut SqliteConnection,
         transactions: Vec<(Split, Transaction)>,
         target_account: &Account,
+        changes: Changes,
         term: &Term,
     ) -> Result<usize> {
         use crate::schema::splits::dsl::{account_guid, splits};
         let len = transactions.len();
+        let Changes::Write(journal) = changes else {
+            term.write_line(&format!(
+                "Dry run, {} splits would be moved to {}",
+                style(len).cyan(),
+                style(target_account).blue()
+            ))?;
+            return self.display(transactions);
+        };
         term.write_line(&format!(
             "Moving {} splits to {}",
             style(len).cyan(),
             style(target_account).blue()
         ))?;
-        for (split, tx) in transactions {
-            println!(
-                "[{}]<{}> - {} - {}",
-                split.account_guid, split.tx_guid, tx, split
-            );
-            let res = diesel::update(splits.find(split.guid))
-                .set(account_guid.eq(&target_account.guid))
-                .execute(connection);
-            assert_eq!(1, res.unwrap());
+        if len > 0 {
+            journal.begin(connection)?;
         }
+        // either every split is moved, or none of them
+        connection.transaction::<_, anyhow::Error, _>(|connection| {
+            for (split, tx) in transactions {
+                println!(
+                    "[{}]<{}> - {} - {}",
+                    split.account_guid, split.tx_guid, tx, split
+                );
+                let updated = diesel::update(splits.find(&split.guid))
+                    .set(account_guid.eq(&target_account.guid))
+                    .execute(connection)?;
+                ensure!(
+                    updated == 1,
+                    "Moving split {} updated {} rows, nothing is moved!",
+                    split.guid,
+                    updated
+                );
+                journal.moved_split(connection, &split.guid, &split.account_guid)?;
+            }
+            Ok(())
+        })?;
         Ok(len)
     }
 }
 
+// The other splits of the transactions, with the name of their account, by transaction
+pub fn load_counter_splits(
+    connection: &mut SqliteConnection,
+    account: &Account,
+    rows: &[(Split, Transaction)],
+) -> Result<BTreeMap<String, Vec<(Split, String)>>> {
+    use crate::schema::{accounts, splits};
+
+    let tx_guids: Vec<&str> = rows
+        .iter()
+        .map(|(split, _)| split.tx_guid.as_str())
+        .collect();
+    let mut result: BTreeMap<String, Vec<(Split, String)>> = BTreeMap::new();
+    for chunk in tx_guids.chunks(500) {
+        let others = splits::table
+            .inner_join(accounts::table)
+            .filter(splits::tx_guid.eq_any(chunk))
+            .filter(splits::account_guid.ne(&account.guid))
+            .select((Split::as_select(), accounts::name))
+            .load::<(Split, String)>(connection)?;
+        for (split, name) in others {
+            result
+                .entry(split.tx_guid.clone())
+                .or_default()
+                .push((split, name));
+        }
+    }
+    Ok(result)
+}
+
 impl From<TransactionsArgs> for TransactionQuery {
     fn from(args: TransactionsArgs) -> Self {
         TransactionQuery {
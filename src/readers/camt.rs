@@ -0,0 +1,227 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use roxmltree::{Document, Node};
+use rust_decimal::Decimal;
+
+use crate::external_models::{Balance, ExternalTransaction, Statement, StatementParser};
+
+// ISO 20022 camt.053 (end of day statement) and camt.054 (debit/credit notification) files.
+// Elements are looked up by their local name, so every version of the schema can be read.
+pub struct CamtFormat;
+
+impl StatementParser for CamtFormat {
+    fn sniff(&self, content: &[u8]) -> u32 {
+        let text = String::from_utf8_lossy(content);
+        if text.contains("BkToCstmrStmt") || text.contains("BkToCstmrDbtCdtNtfctn") {
+            100
+        } else if text.contains("camt.05") {
+            60
+        } else {
+            0
+        }
+    }
+
+    fn parse_statement(&self, content: &[u8]) -> Result<Statement> {
+        let text = std::str::from_utf8(content).context("camt files should be UTF-8 encoded")?;
+        let document = Document::parse(text)?;
+        let reports: Vec<Node> = document
+            .descendants()
+            .filter(|node| node.has_tag_name_local("Stmt") || node.has_tag_name_local("Ntfctn"))
+            .collect();
+        if reports.is_empty() {
+            return Err(anyhow!("Not a camt.053/054 file, no statement found!"));
+        }
+        let mut statement = Statement::default();
+        for report in &reports {
+            for entry in children(*report, "Ntry") {
+                statement.transactions.push(to_external_transaction(entry));
+            }
+        }
+        statement.opening_balance = reports
+            .first()
+            .and_then(|report| find_balance(*report, &["OPBD", "PRCD"]));
+        statement.closing_balance = reports
+            .last()
+            .and_then(|report| find_balance(*report, &["CLBD"]));
+        Ok(statement)
+    }
+}
+
+trait LocalName {
+    fn has_tag_name_local(&self, name: &str) -> bool;
+}
+
+impl LocalName for Node<'_, '_> {
+    fn has_tag_name_local(&self, name: &str) -> bool {
+        self.is_element() && self.tag_name().name() == name
+    }
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name_local(name))
+}
+
+fn find<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter()
+        .try_fold(node, |current, name| children(current, name).next())
+}
+
+fn text(node: Node, path: &[&str]) -> Option<String> {
+    find(node, path)
+        .and_then(|found| found.text())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+}
+
+fn date(node: Node, path: &[&str]) -> Option<NaiveDate> {
+    let element = find(node, path)?;
+    text(element, &["Dt"])
+        .or_else(|| text(element, &["DtTm"]))
+        .and_then(|value| NaiveDate::parse_from_str(value.get(0..10)?, "%Y-%m-%d").ok())
+}
+
+// Amounts are always positive, the CdtDbtInd element tells the direction
+fn signed_amount(node: Node, amount_element: &str) -> Option<Decimal> {
+    let amount = text(node, &[amount_element])?.parse::<Decimal>().ok()?;
+    match text(node, &["CdtDbtInd"]).as_deref() {
+        Some("DBIT") => Some(-amount),
+        _ => Some(amount),
+    }
+}
+
+fn find_balance(report: Node, types: &[&str]) -> Option<Balance> {
+    children(report, "Bal")
+        .find(|balance| {
+            text(*balance, &["Tp", "CdOrPrtry", "Cd"])
+                .is_some_and(|code| types.contains(&code.as_str()))
+        })
+        .and_then(|balance| {
+            Some(Balance {
+                date: date(balance, &["Dt"]),
+                amount: signed_amount(balance, "Amt")?,
+            })
+        })
+}
+
+// Older versions have Chrgs/Amt, newer ones have the total, or a list of charge records
+fn charges(entry: Node) -> Option<Decimal> {
+    let charges =
+        find(entry, &["Chrgs"]).or_else(|| find(entry, &["NtryDtls", "TxDtls", "Chrgs"]))?;
+    let total = text(charges, &["TtlChrgsAndTaxAmt"])
+        .or_else(|| text(charges, &["Amt"]))
+        .and_then(|value| value.parse::<Decimal>().ok());
+    total.or_else(|| {
+        let records: Vec<Decimal> = children(charges, "Rcrd")
+            .filter_map(|record| text(record, &["Amt"]))
+            .filter_map(|value| value.parse::<Decimal>().ok())
+            .collect();
+        if records.is_empty() {
+            None
+        } else {
+            Some(records.into_iter().sum())
+        }
+    })
+}
+
+// The party name is either directly in the element, or in a Pty sub element since version 8
+fn party_name(parties: Node, party: &str) -> Option<String> {
+    text(parties, &[party, "Nm"]).or_else(|| text(parties, &[party, "Pty", "Nm"]))
+}
+
+fn to_external_transaction(entry: Node) -> ExternalTransaction {
+    let booking_date = date(entry, &["BookgDt"]);
+    let amount = signed_amount(entry, "Amt");
+    let details = find(entry, &["NtryDtls", "TxDtls"]);
+    // the counterparty of an outgoing payment is the creditor, of an incoming one is the debtor
+    let (party, party_account) = if amount.is_some_and(|value| value.is_sign_negative()) {
+        ("Cdtr", "CdtrAcct")
+    } else {
+        ("Dbtr", "DbtrAcct")
+    };
+    let parties = details.and_then(|tx| find(tx, &["RltdPties"]));
+    let remittance = details.and_then(|tx| find(tx, &["RmtInf"])).map(|info| {
+        children(info, "Ustrd")
+            .filter_map(|line| line.text())
+            .map(str::trim)
+            .collect::<Vec<&str>>()
+            .join(" ")
+    });
+    let description = remittance
+        .filter(|value| !value.is_empty())
+        .or_else(|| details.and_then(|tx| text(tx, &["RmtInf", "Strd", "CdtrRefInf", "Ref"])))
+        .or_else(|| details.and_then(|tx| text(tx, &["AddtlTxInf"])))
+        .or_else(|| text(entry, &["AddtlNtryInf"]));
+    ExternalTransaction {
+        date: date(entry, &["ValDt"]).or(booking_date),
+        booking_date,
+        amount,
+        category: text(entry, &["BkTxCd", "Prtry", "Cd"]),
+        description,
+        other_account: parties.and_then(|p| {
+            text(p, &[party_account, "Id", "IBAN"])
+                .or_else(|| text(p, &[party_account, "Id", "Othr", "Id"]))
+        }),
+        other_account_name: parties.and_then(|p| party_name(p, party)),
+        textual_date: None,
+        transaction_fee: charges(entry).filter(|fee| !fee.is_zero()),
+        reference: text(entry, &["AcctSvcrRef"])
+            .or_else(|| details.and_then(|tx| text(tx, &["Refs", "AcctSvcrRef"]))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMT053: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Bal><Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">100.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>2024-03-01</Dt></Dt></Bal>
+      <Bal><Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">20.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Dt><Dt>2024-03-01</Dt></Dt></Bal>
+      <Ntry>
+        <Amt Ccy="EUR">120.00</Amt><CdtDbtInd>DBIT</CdtDbtInd>
+        <BookgDt><Dt>2024-03-01</Dt></BookgDt><ValDt><Dt>2024-02-29</Dt></ValDt>
+        <AcctSvcrRef>REF-1</AcctSvcrRef>
+        <Chrgs><Amt Ccy="EUR">1.50</Amt></Chrgs>
+        <NtryDtls><TxDtls>
+          <RltdPties>
+            <Cdtr><Nm>Landlord</Nm></Cdtr>
+            <CdtrAcct><Id><IBAN>AT611904300234573201</IBAN></Id></CdtrAcct>
+          </RltdPties>
+          <RmtInf><Ustrd>Rent</Ustrd><Ustrd>March</Ustrd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    #[test]
+    fn test_parse_camt053() {
+        let statement = CamtFormat.parse_statement(CAMT053.as_bytes()).unwrap();
+        assert_eq!(statement.transactions.len(), 1);
+        let entry = &statement.transactions[0];
+        assert_eq!(entry.amount, Some(Decimal::new(-12000, 2)));
+        assert_eq!(entry.date, NaiveDate::from_ymd_opt(2024, 2, 29));
+        assert_eq!(entry.booking_date, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert_eq!(entry.transaction_fee, Some(Decimal::new(150, 2)));
+        assert_eq!(entry.other_account.as_deref(), Some("AT611904300234573201"));
+        assert_eq!(entry.other_account_name.as_deref(), Some("Landlord"));
+        assert_eq!(entry.description.as_deref(), Some("Rent March"));
+        assert_eq!(
+            statement.opening_balance.map(|balance| balance.amount),
+            Some(Decimal::new(10000, 2))
+        );
+        assert_eq!(
+            statement.closing_balance.map(|balance| balance.amount),
+            Some(Decimal::new(-2000, 2))
+        );
+    }
+}
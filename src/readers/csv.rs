@@ -0,0 +1,162 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use calamine::{Data, Range};
+use encoding_rs::{Encoding, UTF_8};
+
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub skip_rows: usize,
+    pub encoding: &'static Encoding,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            quote: b'"',
+            skip_rows: 0,
+            encoding: UTF_8,
+        }
+    }
+}
+
+impl CsvOptions {
+    pub fn new(
+        delimiter: Option<char>,
+        quote: Option<char>,
+        skip_rows: Option<usize>,
+        encoding: Option<&str>,
+    ) -> Result<Self> {
+        let defaults = CsvOptions::default();
+        let encoding = match encoding {
+            Some(label) => Encoding::for_label(label.as_bytes())
+                .with_context(|| format!("Unknown encoding:'{}'!", label))?,
+            None => defaults.encoding,
+        };
+        Ok(CsvOptions {
+            delimiter: delimiter.map_or(Ok(defaults.delimiter), to_single_byte)?,
+            quote: quote.map_or(Ok(defaults.quote), to_single_byte)?,
+            skip_rows: skip_rows.unwrap_or(defaults.skip_rows),
+            encoding,
+        })
+    }
+}
+
+fn to_single_byte(ch: char) -> Result<u8> {
+    u8::try_from(ch)
+        .ok()
+        .filter(|b| b.is_ascii())
+        .with_context(|| format!("'{}' is not a single byte character!", ch))
+}
+
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+// Guesses the delimiter from the first lines, it's the one, which appears the most times in
+// every line. Quoted fields are not handled, but they rarely change the outcome.
+pub fn sniff_delimiter(bytes: &[u8]) -> Option<char> {
+    let lines: Vec<&[u8]> = bytes
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+        .take(20)
+        .collect();
+    DELIMITERS
+        .iter()
+        .map(|delimiter| {
+            let least = lines
+                .iter()
+                .map(|line| line.iter().filter(|b| *b == delimiter).count())
+                .min()
+                .unwrap_or(0);
+            (least, *delimiter)
+        })
+        .filter(|(least, _)| *least > 0)
+        .max_by_key(|(least, _)| *least)
+        .map(|(_, delimiter)| delimiter as char)
+}
+
+pub fn read_csv(input_file: &str, options: &CsvOptions) -> Result<Range<Data>> {
+    let bytes = fs::read(input_file).with_context(|| format!("Unable to read {}", input_file))?;
+    parse_csv(&bytes, options)
+}
+
+// Converts the file into a sheet-like range, where every non-empty field is a string cell,
+// so the same SheetParser implementations can process it as the spreadsheets.
+pub fn parse_csv(bytes: &[u8], options: &CsvOptions) -> Result<Range<Data>> {
+    let (content, _, had_errors) = options.encoding.decode(bytes);
+    if had_errors {
+        println!(
+            "Invalid characters found while decoding as {}",
+            options.encoding.name()
+        );
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(options.delimiter)
+        .quote(options.quote)
+        .from_reader(content.as_bytes());
+
+    let mut rows = Vec::new();
+    for record in reader.records().skip(options.skip_rows) {
+        rows.push(record?);
+    }
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if rows.is_empty() || width == 0 {
+        return Ok(Range::empty());
+    }
+    let mut range = Range::new((0, 0), ((rows.len() - 1) as u32, (width - 1) as u32));
+    for (row_idx, row) in rows.iter().enumerate() {
+        for (col_idx, field) in row.iter().enumerate() {
+            let value = field.trim();
+            if !value.is_empty() {
+                range.set_value(
+                    (row_idx as u32, col_idx as u32),
+                    Data::String(value.to_owned()),
+                );
+            }
+        }
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_with_header_skip() {
+        let options = CsvOptions::new(Some(';'), None, Some(1), None).unwrap();
+        let range = parse_csv(b"date;amount\n2024-01-05;\"-1 200,50\"\n", &options).unwrap();
+        assert_eq!(range.get_size(), (1, 2));
+        assert_eq!(
+            range.get((0, 1)),
+            Some(&Data::String("-1 200,50".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_windows_1250() {
+        let options = CsvOptions::new(None, None, None, Some("windows-1250")).unwrap();
+        // "Ő" is 0xD5 in Windows-1250
+        let range = parse_csv(b"x,\xd5r\n", &options).unwrap();
+        assert_eq!(range.get((0, 1)), Some(&Data::String("Őr".to_owned())));
+    }
+
+    #[test]
+    fn test_sniff_delimiter() {
+        assert_eq!(sniff_delimiter(b"a;b,c;d\n1;2,5;x\n"), Some(';'));
+        assert_eq!(sniff_delimiter(b"a\tb\n1\t2\n"), Some('\t'));
+        assert_eq!(sniff_delimiter(b"single column\n"), None);
+    }
+
+    #[test]
+    fn test_empty_fields_are_empty_cells() {
+        let range = parse_csv(b"a,,c\nd\n", &CsvOptions::default()).unwrap();
+        assert_eq!(range.get_size(), (2, 3));
+        assert_eq!(range.get((0, 1)), Some(&Data::Empty));
+        assert_eq!(range.get((1, 2)), Some(&Data::Empty));
+    }
+}
@@ -0,0 +1,5 @@
+pub mod camt;
+pub mod csv;
+pub mod mt940;
+pub mod ofx;
+pub mod qif;
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate};
+use encoding_rs::WINDOWS_1252;
+use regex::Regex;
+use rust_decimal::Decimal;
+
+use crate::external_models::{Balance, ExternalTransaction, Statement, StatementParser};
+
+// SWIFT MT940 customer statement, where every :61: statement line, and the following
+// :86: information line is a transaction, and :60F: / :62F: are the opening and closing balances
+pub struct Mt940Format;
+
+lazy_static! {
+    static ref TAG: Regex = Regex::new(r"^:(\d{2}[A-Z]?):(.*)$").unwrap();
+    static ref STATEMENT_LINE: Regex = Regex::new(
+        r"(?s)^(\d{6})(\d{4})?(R?[CD])[A-Z]?(\d+(?:,\d*)?)([NFS][A-Z0-9]{3})([^/\n]*)(?://([^\n]*))?(?:\n(.*))?$"
+    )
+    .unwrap();
+    static ref BALANCE: Regex = Regex::new(r"^([CD])(\d{6})([A-Z]{3})(\d+(?:,\d*)?)").unwrap();
+    static ref SUBFIELD: Regex = Regex::new(r"\?(\d{2})").unwrap();
+}
+
+impl StatementParser for Mt940Format {
+    fn sniff(&self, content: &[u8]) -> u32 {
+        let text = String::from_utf8_lossy(content);
+        let fields = split_fields(&text);
+        let has = |tag: &str| fields.iter().any(|(name, _)| name == tag);
+        match (has("20"), has("61"), has("60F")) {
+            (true, true, _) | (true, _, true) => 100,
+            (true, false, false) => 30,
+            _ => 0,
+        }
+    }
+
+    fn parse_statement(&self, content: &[u8]) -> Result<Statement> {
+        let text = match std::str::from_utf8(content) {
+            Ok(text) => text.to_owned(),
+            Err(_) => WINDOWS_1252.decode(content).0.into_owned(),
+        };
+        let fields = split_fields(&text);
+        if !fields.iter().any(|(tag, _)| tag == "20") {
+            return Err(anyhow!("Not an MT940 file, no :20: field found!"));
+        }
+        let mut statement = Statement::default();
+        for (tag, value) in &fields {
+            match tag.as_str() {
+                "60F" if statement.opening_balance.is_none() => {
+                    statement.opening_balance = parse_balance(value);
+                }
+                "62F" => statement.closing_balance = parse_balance(value),
+                "61" => match parse_statement_line(value) {
+                    Some(transaction) => statement.transactions.push(transaction),
+                    None => println!("Unable to parse statement line: {}", value),
+                },
+                "86" => {
+                    if let Some(transaction) = statement.transactions.last_mut() {
+                        apply_information(transaction, value);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(statement)
+    }
+}
+
+// Returns the (tag, value) pairs, where multi-line values are joined by new lines.
+// The SWIFT envelope, like {1:...}{2:...}{4: and the -} trailer is skipped.
+fn split_fields(text: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for raw_line in text.lines() {
+        let line = raw_line.trim_end();
+        if line.starts_with('{') || line == "-" || line == "-}" || line.is_empty() {
+            continue;
+        }
+        if let Some(caps) = TAG.captures(line) {
+            fields.push((caps[1].to_owned(), caps[2].to_owned()));
+        } else if let Some((_, value)) = fields.last_mut() {
+            value.push('\n');
+            value.push_str(line);
+        }
+    }
+    fields
+}
+
+fn parse_amount(value: &str) -> Option<Decimal> {
+    value.replace(',', ".").parse::<Decimal>().ok()
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%y%m%d").ok()
+}
+
+fn parse_balance(value: &str) -> Option<Balance> {
+    let caps = BALANCE.captures(value)?;
+    let amount = parse_amount(&caps[4])?;
+    Some(Balance {
+        date: parse_date(&caps[2]),
+        amount: if &caps[1] == "D" { -amount } else { amount },
+    })
+}
+
+// The entry date has only month and day, the year comes from the value date,
+// which can be in the previous or next year around new year
+fn entry_date(value_date: NaiveDate, month_day: &str) -> Option<NaiveDate> {
+    let month: u32 = month_day.get(0..2)?.parse().ok()?;
+    let day: u32 = month_day.get(2..4)?.parse().ok()?;
+    let year = match (value_date.month(), month) {
+        (12, 1) => value_date.year() + 1,
+        (1, 12) => value_date.year() - 1,
+        _ => value_date.year(),
+    };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+fn parse_statement_line(value: &str) -> Option<ExternalTransaction> {
+    let caps = STATEMENT_LINE.captures(value)?;
+    let value_date = parse_date(&caps[1])?;
+    let booking_date = caps
+        .get(2)
+        .and_then(|month_day| entry_date(value_date, month_day.as_str()))
+        .or(Some(value_date));
+    let amount = parse_amount(&caps[4])?;
+    // a reversal of a credit is a debit, and the other way around
+    let signed = match &caps[3] {
+        "D" | "RC" => -amount,
+        _ => amount,
+    };
+    let customer_reference = caps[6].trim();
+    let bank_reference = caps.get(7).map(|m| m.as_str().trim()).unwrap_or("");
+    let reference = [bank_reference, customer_reference]
+        .into_iter()
+        .find(|r| !r.is_empty() && *r != "NONREF")
+        .map(str::to_owned);
+    Some(ExternalTransaction {
+        date: Some(value_date),
+        booking_date,
+        amount: Some(signed),
+        category: Some(caps[5].to_owned()),
+        description: caps
+            .get(8)
+            .map(|m| m.as_str().trim().to_owned())
+            .filter(|s| !s.is_empty()),
+        other_account: None,
+        other_account_name: None,
+        textual_date: None,
+        transaction_fee: None,
+        reference,
+    })
+}
+
+// The :86: field is either free text, or structured into ?NN subfields, like the German banks do:
+// ?00 posting text, ?20-?29 and ?60-?63 purpose, ?31 account of the partner, ?32-?33 name
+fn apply_information(transaction: &mut ExternalTransaction, value: &str) {
+    let text = value.replace('\n', "");
+    if !SUBFIELD.is_match(&text) {
+        transaction.description = Some(value.replace('\n', " ").trim().to_owned());
+        return;
+    }
+    let mut subfields: BTreeMap<u32, String> = BTreeMap::new();
+    let positions: Vec<(usize, usize, u32)> = SUBFIELD
+        .captures_iter(&text)
+        .filter_map(|caps| {
+            let whole = caps.get(0)?;
+            Some((whole.start(), whole.end(), caps[1].parse().ok()?))
+        })
+        .collect();
+    for (idx, (_, end, code)) in positions.iter().enumerate() {
+        let next_start = positions
+            .get(idx + 1)
+            .map_or(text.len(), |(start, _, _)| *start);
+        subfields.insert(*code, text[*end..next_start].trim().to_owned());
+    }
+    let join = |codes: &mut dyn Iterator<Item = u32>| -> Option<String> {
+        let joined = codes
+            .filter_map(|code| subfields.get(&code))
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(" ");
+        if joined.is_empty() {
+            None
+        } else {
+            Some(joined)
+        }
+    };
+    if let Some(purpose) = join(&mut (20..=29).chain(60..=63)) {
+        transaction.description = Some(purpose);
+    }
+    if let Some(name) = join(&mut (32..=33)) {
+        transaction.other_account_name = Some(name);
+    }
+    if let Some(account) = join(&mut (31..=31)) {
+        transaction.other_account = Some(account);
+    }
+    if let Some(posting_text) = join(&mut (0..=0)) {
+        transaction.category = Some(posting_text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MT940: &str = "{1:F01BANKDEFFXXXX0000000000}{2:O940}{4:\n\
+        :20:STARTUMS\n\
+        :25:10020030/1234567890\n\
+        :28C:1/1\n\
+        :60F:C231229EUR1000,00\n\
+        :61:2312291230DR25,50NTRFNONREF//B123\n\
+        :86:177?00SEPA UEBERWEISUNG?20Invoice 42?21December?31DE89370400440532013000\n\
+        ?32Office Supplies?33GmbH\n\
+        :61:2401020102C100,NTRFREF1\n\
+        :86:Refund of deposit\n\
+        :62F:C240102EUR1074,50\n\
+        -}";
+
+    #[test]
+    fn test_parse_mt940() {
+        let statement = Mt940Format.parse_statement(MT940.as_bytes()).unwrap();
+        assert_eq!(statement.transactions.len(), 2);
+        let first = &statement.transactions[0];
+        assert_eq!(first.date, NaiveDate::from_ymd_opt(2023, 12, 29));
+        assert_eq!(first.booking_date, NaiveDate::from_ymd_opt(2023, 12, 30));
+        assert_eq!(first.amount, Some(Decimal::new(-2550, 2)));
+        assert_eq!(first.description.as_deref(), Some("Invoice 42 December"));
+        assert_eq!(
+            first.other_account_name.as_deref(),
+            Some("Office Supplies GmbH")
+        );
+        assert_eq!(first.reference.as_deref(), Some("B123"));
+        let second = &statement.transactions[1];
+        assert_eq!(second.amount, Some(Decimal::new(100, 0)));
+        assert_eq!(second.description.as_deref(), Some("Refund of deposit"));
+        assert_eq!(
+            statement.closing_balance.map(|balance| balance.amount),
+            Some(Decimal::new(107450, 2))
+        );
+    }
+}
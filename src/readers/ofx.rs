@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use rust_decimal::Decimal;
+
+use crate::external_models::{Balance, ExternalTransaction, Statement, StatementParser};
+
+// OFX 1.x (SGML) and 2.x (XML) statements, also used by the QFX files
+pub struct OfxFormat;
+
+impl StatementParser for OfxFormat {
+    fn sniff(&self, content: &[u8]) -> u32 {
+        let text = decode(content);
+        match (text.contains("<OFX>"), text.contains("OFXHEADER")) {
+            (true, true) => 100,
+            (true, false) | (false, true) => 80,
+            _ => 0,
+        }
+    }
+
+    fn parse_statement(&self, content: &[u8]) -> Result<Statement> {
+        let text = decode(content);
+        let start = text
+            .find("<OFX>")
+            .ok_or_else(|| anyhow!("Not an OFX file, <OFX> element not found!"))?;
+        let mut result = Vec::new();
+        for block in statement_blocks(&text[start..]) {
+            // the same FITID is the same transaction of the account, even when the bank sends
+            // it again, the other accounts can use the same ids
+            let mut seen_ids = HashSet::new();
+            for fields in aggregate_elements(block, "STMTTRN") {
+                let transaction = to_external_transaction(&fields);
+                if let Some(id) = &transaction.reference
+                    && !seen_ids.insert(id.clone())
+                {
+                    println!("Skipping duplicated transaction {}", id);
+                    continue;
+                }
+                result.push(transaction);
+            }
+        }
+        // the ledger balance is the closing balance of the statement
+        let closing_balance = aggregate_elements(&text[start..], "LEDGERBAL")
+            .first()
+            .and_then(to_balance);
+        Ok(Statement {
+            transactions: result,
+            closing_balance,
+            ..Default::default()
+        })
+    }
+}
+
+// OFX 1.x declares the character set in the header, like CHARSET:1252,
+// OFX 2.x is XML, which is UTF-8, unless the encoding attribute says otherwise
+fn decode(content: &[u8]) -> String {
+    let header_end = content.len().min(1024);
+    let header = String::from_utf8_lossy(&content[..header_end]);
+    let encoding = header_value(&header, "CHARSET:")
+        .and_then(|charset| match charset.as_str() {
+            "1252" => Some(WINDOWS_1252),
+            "NONE" => None,
+            other => Encoding::for_label(other.as_bytes()),
+        })
+        .or_else(|| {
+            header_value(&header, "encoding=\"")
+                .and_then(|label| Encoding::for_label(label.trim_end_matches('"').as_bytes()))
+        })
+        .unwrap_or(UTF_8);
+    encoding.decode(content).0.into_owned()
+}
+
+fn header_value(header: &str, key: &str) -> Option<String> {
+    header.find(key).map(|idx| {
+        header[idx + key.len()..]
+            .chars()
+            .take_while(|ch| !ch.is_whitespace() && *ch != '?')
+            .collect()
+    })
+}
+
+// The statements of the accounts in the file, a bank or a credit card statement each
+fn statement_blocks(text: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = ["<STMTRS>", "<CCSTMTRS>"]
+        .iter()
+        .flat_map(|tag| text.match_indices(tag).map(|(idx, _)| idx))
+        .collect();
+    if starts.is_empty() {
+        return vec![text];
+    }
+    starts.sort_unstable();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(idx, start)| &text[*start..starts.get(idx + 1).copied().unwrap_or(text.len())])
+        .collect()
+}
+
+// Collects the leaf elements of every aggregate with the given name, like STMTTRN. In SGML
+// the leaf elements are not closed, so a value lasts until the next tag, which works for
+// the XML variant too.
+fn aggregate_elements(text: &str, name: &str) -> Vec<BTreeMap<String, String>> {
+    let closing_tag = format!("/{}", name);
+    let mut result = Vec::new();
+    let mut current: Option<BTreeMap<String, String>> = None;
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>') else {
+            break;
+        };
+        let tag = rest[open + 1..open + close].trim();
+        rest = &rest[open + close + 1..];
+        let value_end = rest.find('<').unwrap_or(rest.len());
+        let value = rest[..value_end].trim();
+        match tag {
+            _ if tag == name => current = Some(BTreeMap::new()),
+            _ if tag == closing_tag => {
+                if let Some(fields) = current.take() {
+                    result.push(fields);
+                }
+            }
+            _ => {
+                if let Some(fields) = current.as_mut()
+                    && !tag.starts_with('/')
+                    && !value.is_empty()
+                {
+                    fields
+                        .entry(tag.to_uppercase())
+                        .or_insert_with(|| unescape(value));
+                }
+            }
+        }
+    }
+    result
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+// Dates are in YYYYMMDDHHMMSS.XXX[gmt offset:tz name] format, where only the date is required
+fn parse_date(value: Option<&String>) -> Option<NaiveDate> {
+    value
+        .and_then(|date| date.get(0..8))
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+}
+
+fn parse_amount(value: Option<&String>) -> Option<Decimal> {
+    value.and_then(|amount| amount.replace(',', ".").parse::<Decimal>().ok())
+}
+
+fn to_balance(fields: &BTreeMap<String, String>) -> Option<Balance> {
+    Some(Balance {
+        date: parse_date(fields.get("DTASOF")),
+        amount: parse_amount(fields.get("BALAMT"))?,
+    })
+}
+
+fn to_external_transaction(fields: &BTreeMap<String, String>) -> ExternalTransaction {
+    let posted = parse_date(fields.get("DTPOSTED"));
+    let name = fields.get("NAME").or_else(|| fields.get("PAYEE")).cloned();
+    ExternalTransaction {
+        date: parse_date(fields.get("DTUSER")).or(posted),
+        booking_date: posted,
+        amount: parse_amount(fields.get("TRNAMT")),
+        category: fields.get("TRNTYPE").cloned(),
+        description: fields.get("MEMO").cloned().or_else(|| name.clone()),
+        other_account: fields.get("ACCTID").cloned(),
+        other_account_name: name,
+        textual_date: None,
+        transaction_fee: None,
+        reference: fields.get("FITID").cloned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGML: &str = "OFXHEADER:100\nDATA:OFXSGML\nVERSION:102\nCHARSET:1252\n\n\
+        <OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\n\
+        <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240107120000[-5:EST]<DTUSER>20240105\n\
+        <TRNAMT>-12.50<FITID>A1<NAME>Coffee &amp; Co<MEMO>Card payment</STMTTRN>\n\
+        <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240107<TRNAMT>-12.50<FITID>A1<NAME>Coffee</STMTTRN>\n\
+        <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240108<TRNAMT>100<FITID>A2<NAME>Salary</STMTTRN>\n\
+        </BANKTRANLIST><LEDGERBAL><BALAMT>1234,56<DTASOF>20240131</LEDGERBAL>\n\
+        </STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+
+    const XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <?OFX OFXHEADER=\"200\" VERSION=\"211\"?>\n\
+        <OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS><BANKTRANLIST>\n\
+        <STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20240201</DTPOSTED>\n\
+        <TRNAMT>-3.99</TRNAMT><FITID>X9</FITID><PAYEE><NAME>Bakery</NAME></PAYEE></STMTTRN>\n\
+        </BANKTRANLIST></CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>";
+
+    #[test]
+    fn test_parse_sgml() {
+        let statement = OfxFormat.parse_statement(SGML.as_bytes()).unwrap();
+        assert_eq!(
+            statement.closing_balance,
+            Some(Balance {
+                date: NaiveDate::from_ymd_opt(2024, 1, 31),
+                amount: Decimal::new(123456, 2),
+            })
+        );
+        let transactions = statement.transactions;
+        assert_eq!(transactions.len(), 2);
+        let first = &transactions[0];
+        assert_eq!(first.date, NaiveDate::from_ymd_opt(2024, 1, 5));
+        assert_eq!(first.booking_date, NaiveDate::from_ymd_opt(2024, 1, 7));
+        assert_eq!(first.amount, Some(Decimal::new(-1250, 2)));
+        assert_eq!(first.other_account_name, Some("Coffee & Co".to_owned()));
+        assert_eq!(first.description, Some("Card payment".to_owned()));
+        assert_eq!(transactions[1].date, NaiveDate::from_ymd_opt(2024, 1, 8));
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let transactions = OfxFormat
+            .parse_statement(XML.as_bytes())
+            .unwrap()
+            .transactions;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(
+            transactions[0].other_account_name,
+            Some("Bakery".to_owned())
+        );
+        assert_eq!(transactions[0].reference, Some("X9".to_owned()));
+    }
+
+    #[test]
+    fn test_same_id_in_other_account() {
+        let second_account = "<STMTRS><BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240109<TRNAMT>-7<FITID>A1<NAME>Tea</STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+        let ofx = SGML.replace("</STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>", "</STMTRS>")
+            + second_account;
+        let transactions = OfxFormat
+            .parse_statement(ofx.as_bytes())
+            .unwrap()
+            .transactions;
+        // the resent A1 is dropped only in the first account
+        let names: Vec<Option<&str>> = transactions
+            .iter()
+            .map(|transaction| transaction.other_account_name.as_deref())
+            .collect();
+        assert_eq!(
+            names,
+            vec![Some("Coffee & Co"), Some("Salary"), Some("Tea")]
+        );
+    }
+}
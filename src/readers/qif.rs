@@ -0,0 +1,181 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use encoding_rs::WINDOWS_1252;
+use rust_decimal::Decimal;
+
+use crate::external_models::{ExternalTransaction, Statement, StatementParser};
+
+// Quicken Interchange Format, only the bank, cash, credit card and other asset/liability
+// sections are read, the investment, category and memorized transaction lists are skipped.
+pub struct QifFormat;
+
+const ACCOUNT_SECTIONS: [&str; 5] = ["bank", "cash", "ccard", "oth a", "oth l"];
+
+impl StatementParser for QifFormat {
+    fn sniff(&self, content: &[u8]) -> u32 {
+        let text = String::from_utf8_lossy(content);
+        match text.trim_start().lines().next() {
+            Some(first) if first.starts_with("!Type:") => 100,
+            Some(first) if first.starts_with("!Option:") || first.starts_with("!Account") => 80,
+            _ => 0,
+        }
+    }
+
+    fn parse_statement(&self, content: &[u8]) -> Result<Statement> {
+        let text = match std::str::from_utf8(content) {
+            Ok(text) => text.to_owned(),
+            Err(_) => WINDOWS_1252.decode(content).0.into_owned(),
+        };
+        let mut statement = Statement::default();
+        let mut in_account_section = false;
+        let mut seen_section = false;
+        let mut current = QifRecord::default();
+        for raw_line in text.lines() {
+            let line = raw_line.trim_end();
+            if let Some(header) = line.strip_prefix("!Type:") {
+                in_account_section =
+                    ACCOUNT_SECTIONS.contains(&header.trim().to_lowercase().as_str());
+                seen_section = true;
+                current = QifRecord::default();
+                continue;
+            }
+            if line.starts_with('!') {
+                // like !Account or !Option:AutoSwitch
+                in_account_section = false;
+                continue;
+            }
+            if !in_account_section {
+                continue;
+            }
+            let mut chars = line.chars();
+            let Some(code) = chars.next() else {
+                continue;
+            };
+            let value = chars.as_str().trim();
+            match code {
+                '^' => {
+                    if let Some(transaction) = current.to_external_transaction() {
+                        statement.transactions.push(transaction);
+                    }
+                    current = QifRecord::default();
+                }
+                'D' => current.date = parse_date(value),
+                'T' | 'U' => current.amount = parse_amount(value),
+                'P' => current.payee = non_empty(value),
+                'M' => current.memo = non_empty(value),
+                'L' => current.category = non_empty(value),
+                'N' => current.number = non_empty(value),
+                _ => {}
+            }
+        }
+        if !seen_section {
+            return Err(anyhow!("Not a QIF file, no !Type: header found!"));
+        }
+        Ok(statement)
+    }
+}
+
+#[derive(Default)]
+struct QifRecord {
+    date: Option<NaiveDate>,
+    amount: Option<Decimal>,
+    payee: Option<String>,
+    memo: Option<String>,
+    category: Option<String>,
+    number: Option<String>,
+}
+
+impl QifRecord {
+    fn to_external_transaction(&self) -> Option<ExternalTransaction> {
+        self.amount?;
+        Some(ExternalTransaction {
+            date: self.date,
+            booking_date: None,
+            amount: self.amount,
+            category: self.category.clone(),
+            description: self.memo.clone().or_else(|| self.payee.clone()),
+            other_account: None,
+            other_account_name: self.payee.clone(),
+            textual_date: None,
+            transaction_fee: None,
+            reference: self.number.clone(),
+        })
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+// Quicken writes US dates, like 1/31/2024, 1/31'24 or 01/31/24, other tools use ISO dates
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    let compact: String = value.chars().filter(|ch| !ch.is_whitespace()).collect();
+    // the apostrophe marks the years from 2000, 1/ 5' 4 is 2004
+    if let Some((month_day, year)) = compact.split_once('\'') {
+        let year: i32 = year.parse().ok()?;
+        let year = if year < 100 { 2000 + year } else { year };
+        return NaiveDate::parse_from_str(&format!("{}/{}", month_day, year), "%m/%d/%Y").ok();
+    }
+    // %Y would accept a two digit year too, as the first century
+    let short_year = compact
+        .rsplit('/')
+        .next()
+        .is_some_and(|year| year.len() == 2);
+    let us_format = if short_year { "%m/%d/%y" } else { "%m/%d/%Y" };
+    [us_format, "%Y-%m-%d", "%d.%m.%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(&compact, format).ok())
+}
+
+// Thousand separators are dropped, a comma followed by exactly two digits is a decimal comma
+fn parse_amount(value: &str) -> Option<Decimal> {
+    let digits: String = value.chars().filter(|ch| !ch.is_whitespace()).collect();
+    let decimal_comma = digits
+        .rfind(',')
+        .is_some_and(|idx| digits.len() - idx == 3 && !digits[idx..].contains('.'));
+    let normalized = if decimal_comma {
+        digits.replace('.', "").replace(',', ".")
+    } else {
+        digits.replace(',', "")
+    };
+    normalized.parse::<Decimal>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QIF: &str = "!Type:Bank\nD1/31'24\nT-1,234.56\nPHardware store\nMDrill\nLTools\n^\n\
+        D02/01/2024\nT-12,50\nPBakery\n^\n\
+        !Type:Cat\nNGroceries\nE\n^\n";
+
+    #[test]
+    fn test_parse_bank_section() {
+        let statement = QifFormat.parse_statement(QIF.as_bytes()).unwrap();
+        assert_eq!(statement.transactions.len(), 2);
+        let first = &statement.transactions[0];
+        assert_eq!(first.date, NaiveDate::from_ymd_opt(2024, 1, 31));
+        assert_eq!(first.amount, Some(Decimal::new(-123456, 2)));
+        assert_eq!(first.description.as_deref(), Some("Drill"));
+        assert_eq!(first.other_account_name.as_deref(), Some("Hardware store"));
+        assert_eq!(first.category.as_deref(), Some("Tools"));
+        let second = &statement.transactions[1];
+        assert_eq!(second.amount, Some(Decimal::new(-1250, 2)));
+        assert_eq!(second.description.as_deref(), Some("Bakery"));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day);
+        assert_eq!(parse_date("1/ 5' 4"), date(2004, 1, 5));
+        assert_eq!(parse_date("12/31'99"), date(2099, 12, 31));
+        assert_eq!(parse_date("12/31/99"), date(1999, 12, 31));
+        assert_eq!(parse_date("1/5/2004"), date(2004, 1, 5));
+        assert_eq!(parse_date("2004-01-05"), date(2004, 1, 5));
+        assert_eq!(parse_date("1/5'x"), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use console::{Term, style};
+use diesel::prelude::*;
+use rust_decimal::Decimal;
+
+use crate::journal::Changes;
+use crate::models::{Account, Split};
+use crate::query::transactions::TransactionQuery;
+use crate::utils::format_sqlite_date;
+
+// The reconcile state set on the matched splits
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ReconcileState {
+    Cleared,
+    Reconciled,
+}
+
+impl ReconcileState {
+    pub fn code(self) -> &'static str {
+        match self {
+            ReconcileState::Cleared => "c",
+            ReconcileState::Reconciled => "y",
+        }
+    }
+
+    // A reconciled split is never set back to cleared
+    fn is_upgrade(self, current: &str) -> bool {
+        match self {
+            ReconcileState::Cleared => current == "n",
+            ReconcileState::Reconciled => current == "n" || current == "c",
+        }
+    }
+}
+
+fn is_cleared(split: &Split) -> bool {
+    split.reconcile_state == "c" || split.reconcile_state == "y"
+}
+
+// The balance of the cleared and reconciled splits of the account until the given day,
+// as if the marked splits were reconciled too
+pub fn reconciled_balance(
+    connection: &mut SqliteConnection,
+    account: &Account,
+    marked: &HashSet<&str>,
+    until: NaiveDate,
+) -> Decimal {
+    let query = TransactionQuery {
+        limit: i64::MAX,
+        txid_filter: None,
+        account_filter: Some(account.guid.clone()),
+        description_filter: None,
+        memo_filter: None,
+        before_filter: None,
+        after_filter: None,
+    };
+    query
+        .execute(connection)
+        .iter()
+        .filter(|(split, _)| is_cleared(split) || marked.contains(split.guid.as_str()))
+        .filter(|(_, tx)| tx.posting().is_some_and(|posted| posted.date() <= until))
+        .map(|(split, _)| split.get_quantity_as_decimal())
+        .sum()
+}
+
+// Sets the reconcile state of the splits, which are in a lower state yet, and their reconcile
+// date to the date of the closing balance, on a dry run they are only listed
+pub fn reconcile(
+    connection: &mut SqliteConnection,
+    splits: &[&Split],
+    state: ReconcileState,
+    until: NaiveDate,
+    changes: Changes,
+    term: &Term,
+) -> Result<usize> {
+    let changed: Vec<&&Split> = splits
+        .iter()
+        .filter(|split| state.is_upgrade(&split.reconcile_state))
+        .collect();
+    let Changes::Write(journal) = changes else {
+        term.write_line(&format!(
+            "Dry run, {} splits would be marked as {:?}:",
+            style(changed.len()).cyan(),
+            state
+        ))?;
+        for split in &changed {
+            term.write_line(&format!(" - {} {}", split.guid, split))?;
+        }
+        return Ok(changed.len());
+    };
+    if !changed.is_empty() {
+        journal.begin(connection)?;
+    }
+    let reconcile_date = format_sqlite_date(&until.and_hms_opt(23, 59, 59).unwrap());
+    connection.transaction::<_, anyhow::Error, _>(|connection| {
+        use crate::schema::splits;
+        for split in &changed {
+            diesel::update(splits::table.find(&split.guid))
+                .set((
+                    splits::reconcile_state.eq(state.code()),
+                    splits::reconcile_date.eq(&reconcile_date),
+                ))
+                .execute(connection)?;
+            journal.reconciled_split(
+                connection,
+                &split.guid,
+                &split.reconcile_state,
+                split.reconcile_date.as_deref(),
+            )?;
+        }
+        Ok(())
+    })?;
+    term.write_line(&format!(
+        "Marked {} splits as {:?}",
+        style(changed.len()).cyan(),
+        state
+    ))?;
+    Ok(changed.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::{Journal, undo};
+    use crate::test_fixtures::{account, book};
+
+    fn load_splits(connection: &mut SqliteConnection) -> Vec<Split> {
+        use crate::schema::splits;
+        splits::table
+            .select(Split::as_select())
+            .order(splits::guid)
+            .load(connection)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reconcile_and_undo() {
+        let mut connection = book(&[
+            "INSERT INTO transactions VALUES \
+             ('t1', 'huf', '', '2024-01-01 10:00:00', NULL, 'Salary'), \
+             ('t2', 'huf', '', '2024-01-05 10:00:00', NULL, 'Tesco'), \
+             ('t3', 'huf', '', '2024-02-05 10:00:00', NULL, 'Spar')",
+            "INSERT INTO splits VALUES \
+             ('s1', 't1', 'bank', '', '', 'y', '2024-01-02 10:00:00', 10000, 1, 10000, 1, NULL), \
+             ('s2', 't2', 'bank', '', '', 'n', NULL, -500, 1, -500, 1, NULL), \
+             ('s3', 't3', 'bank', '', '', 'n', NULL, -200, 1, -200, 1, NULL)",
+        ]);
+        let until = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let marked = HashSet::from(["s2", "s3"]);
+        // the split after the closing date is not counted
+        assert_eq!(
+            reconciled_balance(
+                &mut connection,
+                &account("bank", "Bank", "huf"),
+                &marked,
+                until
+            ),
+            Decimal::from(9500)
+        );
+
+        let term = Term::stderr();
+        let splits = load_splits(&mut connection);
+        let journal = Journal::new("test", &term);
+        let changed = reconcile(
+            &mut connection,
+            &[&splits[0], &splits[1]],
+            ReconcileState::Cleared,
+            until,
+            Changes::Write(&journal),
+            &term,
+        )
+        .unwrap();
+        // the reconciled one is not set back to cleared
+        assert_eq!(changed, 1);
+        let reconciled = load_splits(&mut connection);
+        let states: Vec<&str> = reconciled
+            .iter()
+            .map(|split| split.reconcile_state.as_str())
+            .collect();
+        assert_eq!(states, vec!["y", "c", "n"]);
+        assert_eq!(
+            reconciled[1].reconcile_date.as_deref(),
+            Some("2024-01-31 23:59:59")
+        );
+
+        undo(&mut connection, None, false, &term).unwrap();
+        assert_eq!(load_splits(&mut connection), splits);
+    }
+}
@@ -0,0 +1,410 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::external_models::{ExternalTransaction, Matching, TransactionPairing};
+use crate::utils::to_string;
+
+// The format of the report, picked by the extension of the file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn from_path(file: &str) -> Result<Self> {
+        let extension = Path::new(file)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            "html" | "htm" => Ok(ReportFormat::Html),
+            _ => Err(anyhow!(
+                "Unknown report format of {}, use .json, .csv or .html!",
+                file
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementRow {
+    // the date used for matching
+    pub date: String,
+    pub booking_date: String,
+    pub amount: Option<Decimal>,
+    pub description: String,
+}
+
+impl StatementRow {
+    pub fn new(transaction: &ExternalTransaction, matching: Matching) -> Self {
+        StatementRow {
+            date: to_string(transaction.get_matching_date(matching)),
+            booking_date: to_string(transaction.booking_date),
+            amount: transaction.amount,
+            description: transaction
+                .get_description_or_category()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookRow {
+    pub date: String,
+    pub amount: Decimal,
+    pub description: String,
+    pub memo: String,
+    pub transaction: String,
+    pub split: String,
+}
+
+impl BookRow {
+    pub fn new(pairing: &TransactionPairing) -> Self {
+        let transaction = pairing.transaction();
+        let split = pairing.split();
+        BookRow {
+            date: to_string(transaction.posting().map(|posted| posted.date())),
+            amount: pairing.amount(),
+            description: transaction.description.clone().unwrap_or_default(),
+            memo: split.memo.clone(),
+            transaction: transaction.guid.clone(),
+            split: split.guid.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchedPair {
+    pub statement: StatementRow,
+    pub book: BookRow,
+    // how many days later the transaction is booked than the date of the statement row
+    pub days: Option<i64>,
+    pub ambiguous: bool,
+    // the pairs matched together by their sum
+    pub group: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CorrelationReport {
+    pub account: String,
+    pub from: String,
+    pub to: String,
+    pub matched: Vec<MatchedPair>,
+    pub external_only: Vec<StatementRow>,
+    pub book_only: Vec<BookRow>,
+    // the rows imported by an earlier run, whose transaction is in an other account now
+    pub imported_earlier: Vec<StatementRow>,
+}
+
+impl CorrelationReport {
+    pub fn add_pairing(&mut self, pairing: &TransactionPairing, matching: Matching) {
+        let booked = pairing.transaction().posting().map(|posted| posted.date());
+        for external in pairing.externals() {
+            let days = booked
+                .zip(external.get_matching_date(matching))
+                .map(|(booked, date)| (booked - date).num_days());
+            self.matched.push(MatchedPair {
+                statement: StatementRow::new(&external, matching),
+                book: BookRow::new(pairing),
+                days,
+                ambiguous: pairing.is_ambiguous(),
+                group: pairing.group(),
+            });
+        }
+    }
+
+    pub fn write(&self, file: &str) -> Result<()> {
+        let content = match ReportFormat::from_path(file)? {
+            ReportFormat::Json => serde_json::to_string_pretty(self)?,
+            ReportFormat::Csv => self.to_csv()?,
+            ReportFormat::Html => self.to_html(),
+        };
+        fs::write(file, content).with_context(|| format!("Unable to write the report {}", file))
+    }
+
+    // Every row of the report in one table, where the status tells the kind of the row
+    fn to_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record([
+            "status",
+            "date",
+            "booking_date",
+            "amount",
+            "description",
+            "book_date",
+            "book_amount",
+            "book_description",
+            "memo",
+            "days",
+            "ambiguous",
+            "group",
+            "transaction",
+            "split",
+        ])?;
+        let empty_statement = ["", "", "", ""].map(str::to_owned);
+        let empty_book = ["", "", "", "", "", ""].map(str::to_owned);
+        let statement_fields = |row: &StatementRow| {
+            [
+                row.date.clone(),
+                row.booking_date.clone(),
+                row.amount
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+                row.description.clone(),
+            ]
+        };
+        let book_fields = |row: &BookRow| {
+            [
+                row.date.clone(),
+                row.amount.to_string(),
+                row.description.clone(),
+                row.memo.clone(),
+                row.transaction.clone(),
+                row.split.clone(),
+            ]
+        };
+        let mut write = |status: &str,
+                         statement: [String; 4],
+                         book: [String; 6],
+                         pair: [String; 3]|
+         -> Result<()> {
+            let [
+                book_date,
+                book_amount,
+                book_description,
+                memo,
+                transaction,
+                split,
+            ] = book;
+            let mut record = vec![status.to_owned()];
+            record.extend(statement);
+            record.extend([book_date, book_amount, book_description, memo]);
+            record.extend(pair);
+            record.extend([transaction, split]);
+            writer.write_record(record)?;
+            Ok(())
+        };
+        for pair in &self.matched {
+            write(
+                "matched",
+                statement_fields(&pair.statement),
+                book_fields(&pair.book),
+                [
+                    pair.days.map(|days| days.to_string()).unwrap_or_default(),
+                    pair.ambiguous.to_string(),
+                    pair.group
+                        .map(|group| group.to_string())
+                        .unwrap_or_default(),
+                ],
+            )?;
+        }
+        for row in &self.external_only {
+            write(
+                "external_only",
+                statement_fields(row),
+                empty_book.clone(),
+                Default::default(),
+            )?;
+        }
+        for row in &self.book_only {
+            write(
+                "book_only",
+                empty_statement.clone(),
+                book_fields(row),
+                Default::default(),
+            )?;
+        }
+        for row in &self.imported_earlier {
+            write(
+                "imported_earlier",
+                statement_fields(row),
+                empty_book.clone(),
+                Default::default(),
+            )?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+
+    fn to_html(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Correlation of {}</title>\n<style>\n\
+             body {{ font-family: sans-serif; margin: 2em; }}\n\
+             table {{ border-collapse: collapse; margin-bottom: 2em; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 0.2em 0.6em; }}\n\
+             th {{ background: #eee; }}\n\
+             td.amount {{ text-align: right; }}\n\
+             tr.ambiguous {{ background: #fff3cd; }}\n\
+             </style>\n</head>\n<body>",
+            escape(&self.account)
+        );
+        let _ = writeln!(
+            out,
+            "<h1>Correlation of {}</h1>\n<p>Between {} and {}: {} matched pairs, \
+             {} rows only in the statement, {} splits only in the book, \
+             {} rows imported earlier into other accounts.</p>",
+            escape(&self.account),
+            escape(&self.from),
+            escape(&self.to),
+            self.matched.len(),
+            self.external_only.len(),
+            self.book_only.len(),
+            self.imported_earlier.len()
+        );
+        out.push_str(
+            "<h2>Matched pairs</h2>\n<table>\n<tr><th>Date</th><th>Amount</th>\
+             <th>Description</th><th>Booked</th><th>Amount</th><th>Description</th>\
+             <th>Days</th><th>Group</th></tr>\n",
+        );
+        for pair in &self.matched {
+            let class = if pair.ambiguous {
+                " class=\"ambiguous\""
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                out,
+                "<tr{}>{}{}<td>{}</td><td>{}</td></tr>",
+                class,
+                statement_cells(&pair.statement),
+                book_cells(&pair.book),
+                pair.days.map(|days| days.to_string()).unwrap_or_default(),
+                pair.group
+                    .map(|group| group.to_string())
+                    .unwrap_or_default()
+            );
+        }
+        out.push_str(
+            "</table>\n<h2>Only in the statement</h2>\n<table>\n\
+             <tr><th>Date</th><th>Amount</th><th>Description</th></tr>\n",
+        );
+        for row in &self.external_only {
+            let _ = writeln!(out, "<tr>{}</tr>", statement_cells(row));
+        }
+        out.push_str(
+            "</table>\n<h2>Only in the book</h2>\n<table>\n\
+             <tr><th>Booked</th><th>Amount</th><th>Description</th></tr>\n",
+        );
+        for row in &self.book_only {
+            let _ = writeln!(out, "<tr>{}</tr>", book_cells(row));
+        }
+        out.push_str(
+            "</table>\n<h2>Imported earlier into other accounts</h2>\n<table>\n\
+             <tr><th>Date</th><th>Amount</th><th>Description</th></tr>\n",
+        );
+        for row in &self.imported_earlier {
+            let _ = writeln!(out, "<tr>{}</tr>", statement_cells(row));
+        }
+        out.push_str("</table>\n</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn statement_cells(row: &StatementRow) -> String {
+    format!(
+        "<td>{}</td><td class=\"amount\">{}</td><td>{}</td>",
+        escape(&row.date),
+        row.amount
+            .map(|amount| amount.to_string())
+            .unwrap_or_default(),
+        escape(&row.description)
+    )
+}
+
+fn book_cells(row: &BookRow) -> String {
+    format!(
+        "<td>{}</td><td class=\"amount\">{}</td><td>{}</td>",
+        escape(&row.date),
+        row.amount,
+        escape(&row.description)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> CorrelationReport {
+        let statement = |description: &str| StatementRow {
+            date: "2024-01-05".to_owned(),
+            booking_date: String::new(),
+            amount: Some(Decimal::new(-50050, 2)),
+            description: description.to_owned(),
+        };
+        let book = |description: &str| BookRow {
+            date: "2024-01-07".to_owned(),
+            amount: Decimal::new(-50050, 2),
+            description: description.to_owned(),
+            memo: String::new(),
+            transaction: "t1".to_owned(),
+            split: "s1".to_owned(),
+        };
+        CorrelationReport {
+            account: "Bank".to_owned(),
+            from: "2024-01-01".to_owned(),
+            to: "2024-01-31".to_owned(),
+            matched: vec![MatchedPair {
+                statement: statement("Tesco"),
+                book: book("Tesco"),
+                days: Some(2),
+                ambiguous: false,
+                group: None,
+            }],
+            external_only: vec![statement("Fish & <Chips>")],
+            book_only: vec![book("Rent")],
+            imported_earlier: vec![statement("Moved")],
+        }
+    }
+
+    #[test]
+    fn test_report_formats() {
+        assert_eq!(
+            ReportFormat::from_path("out/May.HTM").unwrap(),
+            ReportFormat::Html
+        );
+        assert!(ReportFormat::from_path("report.txt").is_err());
+
+        let report = report();
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap();
+        assert_eq!(json["matched"][0]["days"], 2);
+        assert_eq!(json["matched"][0]["book"]["amount"], "-500.50");
+
+        let csv = report.to_csv().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[1],
+            "matched,2024-01-05,,-500.50,Tesco,2024-01-07,-500.50,Tesco,,2,false,,t1,s1"
+        );
+        assert!(lines[3].starts_with("book_only,,,,,2024-01-07,-500.50,Rent"));
+        assert_eq!(
+            lines[4],
+            "imported_earlier,2024-01-05,,-500.50,Moved,,,,,,,,,"
+        );
+
+        let html = report.to_html();
+        assert!(html.contains("Fish &amp; &lt;Chips&gt;"));
+        assert!(html.contains("<td>2</td>"));
+        assert!(html.contains("1 rows imported earlier into other accounts"));
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use console::{Alignment, Key, Term, pad_str, style};
+use rust_decimal::Decimal;
+
+use crate::external_models::{ExternalTransaction, TransactionPairing};
+use crate::models::Account;
+use crate::utils::to_string;
+
+// The lists of the review screen, the two sides of the missing transactions, and the pairs
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Pane {
+    Statement,
+    Book,
+    Pairs,
+}
+
+impl Pane {
+    fn index(self) -> usize {
+        match self {
+            Pane::Statement => 0,
+            Pane::Book => 1,
+            Pane::Pairs => 2,
+        }
+    }
+
+    fn next(self) -> Pane {
+        match self {
+            Pane::Statement => Pane::Book,
+            Pane::Book => Pane::Pairs,
+            Pane::Pairs => Pane::Statement,
+        }
+    }
+
+    fn previous(self) -> Pane {
+        self.next().next()
+    }
+}
+
+// A statement row missing from the book, and how it is added
+#[derive(Clone)]
+pub struct ReviewedRow<'a> {
+    pub row: usize,
+    pub counter: Option<&'a Account>,
+    pub description: String,
+    pub add: bool,
+}
+
+// The result of the review, the pairings of the correlator are changed in place
+pub struct ReviewOutcome<'a> {
+    // the rows missing from the book, in the order of the statement
+    pub missing: Vec<ReviewedRow<'a>>,
+}
+
+pub struct Review<'a> {
+    title: String,
+    statement: &'a [ExternalTransaction],
+    pairings: Vec<&'a TransactionPairing>,
+    // the counter accounts to choose from, by their full name
+    accounts: &'a [(String, Account)],
+    // the proposal for every statement row, used when a row is unpaired
+    proposals: Vec<ReviewedRow<'a>>,
+    missing: Vec<ReviewedRow<'a>>,
+    paired: Vec<(usize, String)>,
+    // the statement rows to pair with the next chosen book split
+    marked: BTreeSet<usize>,
+    pane: Pane,
+    selected: [usize; 3],
+    message: String,
+}
+
+impl<'a> Review<'a> {
+    pub fn new(
+        title: String,
+        statement: &'a [ExternalTransaction],
+        pairings: Vec<&'a TransactionPairing>,
+        accounts: &'a [(String, Account)],
+        proposals: Vec<ReviewedRow<'a>>,
+        unmatched_rows: &[usize],
+    ) -> Self {
+        let missing = unmatched_rows
+            .iter()
+            .map(|row| proposals[*row].clone())
+            .collect();
+        Review {
+            title,
+            statement,
+            pairings,
+            accounts,
+            proposals,
+            missing,
+            paired: Vec::new(),
+            marked: BTreeSet::new(),
+            pane: Pane::Statement,
+            selected: [0; 3],
+            message: String::new(),
+        }
+    }
+
+    fn book_only(&self) -> Vec<&'a TransactionPairing> {
+        self.pairings
+            .iter()
+            .copied()
+            .filter(|pairing| pairing.is_not_matched())
+            .collect()
+    }
+
+    fn pairs(&self) -> Vec<&'a TransactionPairing> {
+        self.pairings
+            .iter()
+            .copied()
+            .filter(|pairing| !pairing.is_not_matched())
+            .collect()
+    }
+
+    fn len(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::Statement => self.missing.len(),
+            Pane::Book => self.book_only().len(),
+            Pane::Pairs => self.pairs().len(),
+        }
+    }
+
+    fn select(&mut self, pane: Pane, index: usize) {
+        self.selected[pane.index()] = index.min(self.len(pane).saturating_sub(1));
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let current = self.selected[self.pane.index()];
+        self.select(self.pane, current.saturating_add_signed(delta));
+    }
+
+    fn selected_row(&mut self) -> Option<&mut ReviewedRow<'a>> {
+        self.missing.get_mut(self.selected[Pane::Statement.index()])
+    }
+
+    fn toggle_add(&mut self) {
+        if let Some(row) = self.selected_row() {
+            row.add = !row.add;
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        let Some(row) = self.selected_row().map(|row| row.row) else {
+            return;
+        };
+        if !self.marked.remove(&row) {
+            self.marked.insert(row);
+        }
+    }
+
+    // Pairs the marked statement rows with the selected split of the book
+    fn pair(&mut self) {
+        let Some(pairing) = self
+            .book_only()
+            .get(self.selected[Pane::Book.index()])
+            .copied()
+        else {
+            return;
+        };
+        if self.marked.is_empty() {
+            self.message = "Mark the statement rows to pair with p first".to_owned();
+            return;
+        }
+        let rows = std::mem::take(&mut self.marked);
+        let mut sum = Decimal::ZERO;
+        for row in &rows {
+            pairing.pair_with(*row, &self.statement[*row]);
+            sum += self.statement[*row].amount.unwrap_or_default();
+            self.paired.push((*row, pairing.transaction().guid.clone()));
+        }
+        if rows.len() > 1 {
+            let group = self
+                .pairings
+                .iter()
+                .filter_map(|pairing| pairing.group())
+                .max()
+                .map_or(0, |group| group + 1);
+            pairing.set_group(group);
+        }
+        self.missing.retain(|missing| !rows.contains(&missing.row));
+        self.message = if sum == pairing.amount() {
+            format!("Paired {} rows with {}", rows.len(), pairing)
+        } else {
+            format!(
+                "Paired {} rows with {}, the amounts differ by {}",
+                rows.len(),
+                pairing,
+                pairing.amount() - sum
+            )
+        };
+        self.select(Pane::Statement, self.selected[Pane::Statement.index()]);
+        self.select(Pane::Book, self.selected[Pane::Book.index()]);
+    }
+
+    // Unpairs the selected pair, with the other pairs of its group
+    fn unpair(&mut self) {
+        let Some(pairing) = self
+            .pairs()
+            .get(self.selected[Pane::Pairs.index()])
+            .copied()
+        else {
+            return;
+        };
+        let unpaired: Vec<&TransactionPairing> = match pairing.group() {
+            Some(group) => self
+                .pairings
+                .iter()
+                .copied()
+                .filter(|other| other.group() == Some(group))
+                .collect(),
+            None => vec![pairing],
+        };
+        let mut rows = BTreeSet::new();
+        for pairing in unpaired {
+            rows.extend(pairing.rows());
+            pairing.unpair();
+        }
+        self.paired.retain(|(row, _)| !rows.contains(row));
+        for row in &rows {
+            self.missing.push(self.proposals[*row].clone());
+        }
+        self.missing.sort_by_key(|missing| missing.row);
+        self.message = format!("Unpaired {} rows", rows.len());
+        self.select(Pane::Pairs, self.selected[Pane::Pairs.index()]);
+    }
+
+    // Sets the counter account of the selected row, by the full name of the account, or an
+    // unique part of it
+    fn set_counter(&mut self, name: &str) {
+        let name = name.trim();
+        let exact: Vec<&'a Account> = self
+            .accounts
+            .iter()
+            .filter(|(full_name, _)| full_name.eq_ignore_ascii_case(name))
+            .map(|(_, account)| account)
+            .collect();
+        let found = if exact.is_empty() {
+            let name = name.to_lowercase();
+            self.accounts
+                .iter()
+                .filter(|(full_name, _)| full_name.to_lowercase().contains(&name))
+                .map(|(_, account)| account)
+                .collect()
+        } else {
+            exact
+        };
+        match found.as_slice() {
+            [account] => {
+                let account = *account;
+                if let Some(row) = self.selected_row() {
+                    row.counter = Some(account);
+                    row.add = true;
+                }
+                self.message = format!("The counter account is {}", account.name);
+            }
+            [] => self.message = format!("No account matches '{}'", name),
+            found => {
+                self.message = format!("{} accounts match '{}'", found.len(), name);
+            }
+        }
+    }
+
+    fn set_description(&mut self, description: &str) {
+        if let Some(row) = self.selected_row() {
+            // the line editor keeps the control keys it does not know
+            row.description = description
+                .chars()
+                .filter(|c| !c.is_control())
+                .collect::<String>()
+                .trim()
+                .to_owned();
+        }
+    }
+
+    fn counter_name(&self, row: &ReviewedRow) -> String {
+        row.counter.map_or("?".to_owned(), |account| {
+            self.accounts
+                .iter()
+                .find(|(_, other)| other.guid == account.guid)
+                .map_or(account.name.clone(), |(full_name, _)| full_name.clone())
+        })
+    }
+
+    fn statement_line(&self, row: &ReviewedRow) -> String {
+        let transaction = &self.statement[row.row];
+        format!(
+            "{}{} {} {:>12} {} -> {}",
+            if row.add { "[+]" } else { "[ ]" },
+            if self.marked.contains(&row.row) {
+                "*"
+            } else {
+                " "
+            },
+            to_string(transaction.date),
+            amount(transaction),
+            row.description,
+            self.counter_name(row)
+        )
+    }
+
+    fn book_line(pairing: &TransactionPairing) -> String {
+        let transaction = pairing.transaction();
+        format!(
+            "{} {:>12} {} {}",
+            to_string(transaction.posting().map(|posted| posted.date())),
+            pairing.amount(),
+            transaction.description.as_deref().unwrap_or_default(),
+            pairing.split().memo
+        )
+    }
+
+    fn pair_line(&self, pairing: &TransactionPairing) -> String {
+        let rows = pairing.rows();
+        let externals: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let transaction = &self.statement[*row];
+                format!(
+                    "{} {} {}",
+                    to_string(transaction.date),
+                    amount(transaction),
+                    transaction
+                        .get_description_or_category()
+                        .unwrap_or_default()
+                )
+            })
+            .collect();
+        let mut line = format!("{} = {}", externals.join(" + "), Self::book_line(pairing));
+        if let Some(group) = pairing.group() {
+            line.push_str(&format!(" (group {})", group));
+        }
+        if pairing.is_ambiguous() {
+            line.push_str(" (ambiguous)");
+        }
+        if self.paired.iter().any(|(row, _)| rows.contains(row)) {
+            line.push_str(" (by hand)");
+        }
+        line
+    }
+
+    fn help(&self) -> &'static str {
+        match self.pane {
+            Pane::Statement => {
+                "space add/skip  c counter account  e description  p mark to pair  tab next  w write  q quit"
+            }
+            Pane::Book => "p pair with the marked rows  tab next  w write  q quit",
+            Pane::Pairs => "u unpair  tab next  w write  q quit",
+        }
+    }
+
+    // The lines of a pane, its header and the items scrolled to the selected one
+    fn pane_lines(
+        &self,
+        pane: Pane,
+        header: &str,
+        items: Vec<String>,
+        height: usize,
+        width: usize,
+    ) -> Vec<String> {
+        let fit = |text: &str| pad_str(text, width, Alignment::Left, Some("~")).into_owned();
+        let focused = self.pane == pane;
+        let mut lines = vec![if focused {
+            style(fit(header)).bold().reverse().to_string()
+        } else {
+            style(fit(header)).bold().to_string()
+        }];
+        let visible = height.saturating_sub(1);
+        let selected = self.selected[pane.index()];
+        let offset = (selected + 1).saturating_sub(visible);
+        for (index, item) in items.iter().enumerate().skip(offset).take(visible) {
+            lines.push(if focused && index == selected {
+                style(fit(item)).reverse().to_string()
+            } else {
+                fit(item)
+            });
+        }
+        lines.resize(height, fit(""));
+        lines
+    }
+
+    fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let book_only = self.book_only();
+        let pairs = self.pairs();
+        let body = height.saturating_sub(3).max(4);
+        let top = body / 2;
+        let left = width.saturating_sub(1) / 2;
+        let right = width.saturating_sub(left + 1);
+        let statement = self.pane_lines(
+            Pane::Statement,
+            &format!("Only in the statement ({})", self.missing.len()),
+            self.missing
+                .iter()
+                .map(|row| self.statement_line(row))
+                .collect(),
+            top,
+            left,
+        );
+        let book = self.pane_lines(
+            Pane::Book,
+            &format!("Only in the book ({})", book_only.len()),
+            book_only
+                .iter()
+                .map(|pairing| Self::book_line(pairing))
+                .collect(),
+            top,
+            right,
+        );
+        let mut lines = vec![
+            style(pad_str(&self.title, width, Alignment::Left, Some("~")))
+                .cyan()
+                .to_string(),
+        ];
+        lines.extend(
+            statement
+                .into_iter()
+                .zip(book)
+                .map(|(statement, book)| format!("{}|{}", statement, book)),
+        );
+        lines.extend(
+            self.pane_lines(
+                Pane::Pairs,
+                &format!("Pairs ({})", pairs.len()),
+                pairs
+                    .iter()
+                    .map(|pairing| self.pair_line(pairing))
+                    .collect(),
+                body - top,
+                width,
+            ),
+        );
+        lines.push(
+            style(pad_str(&self.message, width, Alignment::Left, Some("~")))
+                .yellow()
+                .to_string(),
+        );
+        lines.push(pad_str(self.help(), width, Alignment::Left, Some("~")).into_owned());
+        lines
+    }
+
+    fn draw(&self, term: &Term) -> Result<()> {
+        let (height, width) = term.size();
+        term.move_cursor_to(0, 0)?;
+        let lines = self.render(usize::from(width), usize::from(height));
+        term.write_str(&lines.join("\n"))?;
+        Ok(())
+    }
+
+    // Asks for a text on the last line of the screen
+    fn prompt(&self, term: &Term, question: &str, initial: &str) -> Result<String> {
+        let (height, _) = term.size();
+        term.move_cursor_to(0, usize::from(height).saturating_sub(1))?;
+        term.clear_line()?;
+        term.write_str(question)?;
+        term.show_cursor()?;
+        let answer = term.read_line_initial_text(initial);
+        term.hide_cursor()?;
+        Ok(answer?)
+    }
+
+    fn confirm_quit(&self, term: &Term) -> Result<bool> {
+        let (height, _) = term.size();
+        term.move_cursor_to(0, usize::from(height).saturating_sub(1))?;
+        term.clear_line()?;
+        term.write_str("Quit without writing anything? [y/N]")?;
+        Ok(matches!(term.read_key()?, Key::Char('y' | 'Y')))
+    }
+
+    fn handle_keys(&mut self, term: &Term) -> Result<bool> {
+        loop {
+            self.draw(term)?;
+            let key = term.read_key()?;
+            self.message.clear();
+            match (self.pane, key) {
+                (_, Key::Tab | Key::ArrowRight) => self.pane = self.pane.next(),
+                (_, Key::BackTab | Key::ArrowLeft) => self.pane = self.pane.previous(),
+                (_, Key::ArrowUp | Key::Char('k')) => self.move_selection(-1),
+                (_, Key::ArrowDown | Key::Char('j')) => self.move_selection(1),
+                (_, Key::PageUp) => self.move_selection(-10),
+                (_, Key::PageDown) => self.move_selection(10),
+                (_, Key::Home) => self.select(self.pane, 0),
+                (_, Key::End) => self.select(self.pane, usize::MAX),
+                (Pane::Statement, Key::Char(' ')) => self.toggle_add(),
+                (Pane::Statement, Key::Char('p')) => self.toggle_mark(),
+                (Pane::Statement, Key::Char('c')) => {
+                    let Some(current) = self.missing.get(self.selected[Pane::Statement.index()])
+                    else {
+                        continue;
+                    };
+                    let initial = current.counter.map(|_| self.counter_name(current));
+                    let name = self.prompt(
+                        term,
+                        "Counter account: ",
+                        initial.as_deref().unwrap_or_default(),
+                    )?;
+                    self.set_counter(&name);
+                }
+                (Pane::Statement, Key::Char('e')) => {
+                    let Some(current) = self.missing.get(self.selected[Pane::Statement.index()])
+                    else {
+                        continue;
+                    };
+                    let description =
+                        self.prompt(term, "Description: ", &current.description.clone())?;
+                    self.set_description(&description);
+                }
+                (Pane::Book, Key::Char('p')) => self.pair(),
+                (Pane::Pairs, Key::Char('u')) => self.unpair(),
+                (_, Key::Char('w')) => return Ok(true),
+                (_, Key::Char('q') | Key::Escape | Key::CtrlC) if self.confirm_quit(term)? => {
+                    return Ok(false);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Shows the review screen until the changes are written, or the review is quit
+    pub fn run(mut self, term: &Term) -> Result<Option<ReviewOutcome<'a>>> {
+        if !term.is_term() {
+            return Err(anyhow!("The review requires an interactive terminal!"));
+        }
+        term.hide_cursor()?;
+        term.clear_screen()?;
+        let result = self.handle_keys(term);
+        term.show_cursor()?;
+        term.clear_screen()?;
+        Ok(result?.then(|| self.outcome()))
+    }
+
+    fn outcome(self) -> ReviewOutcome<'a> {
+        ReviewOutcome {
+            missing: self.missing,
+        }
+    }
+}
+
+fn amount(transaction: &ExternalTransaction) -> String {
+    transaction
+        .amount
+        .map(|amount| amount.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{account, booked, external};
+
+    fn pairing(guid: &str, day: u32, amount: i64, description: &str) -> TransactionPairing {
+        TransactionPairing::new(booked(guid, day, amount, description))
+    }
+
+    #[test]
+    fn test_review_changes() {
+        let statement = vec![
+            external(5, -5000, "Tesco"),
+            external(10, -700, "Spar 1"),
+            external(10, -500, "Spar 2"),
+        ];
+        let tesco = pairing("tx1", 5, -5000, "Tesco");
+        let spar = pairing("tx2", 11, -1200, "Spar");
+        tesco.pair_with(0, &statement[0]);
+        let accounts = vec![
+            ("Expenses:Food".to_owned(), account("food", "Food", "huf")),
+            ("Expenses:Fees".to_owned(), account("fees", "Fees", "huf")),
+        ];
+        let proposals = (0..statement.len())
+            .map(|row| ReviewedRow {
+                row,
+                counter: None,
+                description: statement[row].description.clone().unwrap(),
+                add: false,
+            })
+            .collect();
+        let mut review = Review::new(
+            "Bank".to_owned(),
+            &statement,
+            vec![&tesco, &spar],
+            &accounts,
+            proposals,
+            &[1, 2],
+        );
+        let screen = review.render(100, 12);
+        assert_eq!(screen.len(), 12);
+        assert!(screen[2].contains("Spar 1") && screen[2].contains("Spar"));
+
+        // both Spar rows are paired with the one booked transaction
+        review.toggle_mark();
+        review.move_selection(1);
+        review.toggle_mark();
+        review.pair();
+        assert_eq!(spar.rows(), vec![1, 2]);
+        assert_eq!(spar.group(), Some(0));
+        assert!(review.missing.is_empty());
+
+        // the Tesco row is added to the food account instead
+        review.pane = Pane::Pairs;
+        review.unpair();
+        assert!(tesco.is_not_matched());
+        review.set_counter("fe");
+        assert_eq!(
+            review.missing[0].counter.map(|a| a.name.as_str()),
+            Some("Fees")
+        );
+        review.set_counter("e");
+        assert!(review.message.starts_with("2 accounts"));
+        review.set_counter("expenses:food");
+        review.set_description("Tesco Extra");
+        let outcome = review.outcome();
+        assert_eq!(outcome.missing.len(), 1);
+        let row = &outcome.missing[0];
+        assert_eq!((row.row, row.add), (0, true));
+        assert_eq!(row.counter.map(|a| a.guid.as_str()), Some("food"));
+        assert_eq!(row.description, "Tesco Extra");
+    }
+}
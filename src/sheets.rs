@@ -25,20 +25,18 @@ pub fn cell_to_english_date(cell: &Data) -> Option<NaiveDate> {
 pub fn cell_to_date_raw(cell: &Data, format: &str) -> Option<NaiveDate> {
     match cell {
         Data::String(str) => NaiveDate::parse_from_str(str, format).ok(),
-        Data::DateTime(date_time) => {
-            println!("Unexpected format: date time : {date_time}");
-            panic!("Wrong type!");
-        }
+        // a real date cell of a spreadsheet has no format to check
+        Data::DateTime(date_time) => date_time.as_datetime().map(|datetime| datetime.date()),
         Data::DateTimeIso(date_time) => NaiveDate::parse_from_str(date_time, "%Y-%m-%d").ok(),
         _ => None,
     }
 }
 
 pub fn cell_to_datetime(cell: &Data) -> Option<NaiveDateTime> {
-    if let Data::String(str) = cell {
-        NaiveDateTime::parse_from_str(str, "%Y.%m.%d. %H:%M:%S").ok()
-    } else {
-        None
+    match cell {
+        Data::String(str) => NaiveDateTime::parse_from_str(str, "%Y.%m.%d. %H:%M:%S").ok(),
+        Data::DateTime(date_time) => date_time.as_datetime(),
+        _ => None,
     }
 }
 
@@ -62,3 +60,18 @@ pub fn cell_to_decimal(cell: &Data) -> Option<Decimal> {
         _ => None,
     }
 }
+
+// Amounts exported as text, like "-1 234,50" with decimal comma, or "-1,234.50" with decimal point
+pub fn cell_to_localized_decimal(cell: &Data, decimal_comma: bool) -> Option<Decimal> {
+    if let Data::String(string) = cell {
+        let digits: String = string.chars().filter(|c| !c.is_whitespace()).collect();
+        let normalized = if decimal_comma {
+            digits.replace('.', "").replace(',', ".")
+        } else {
+            digits.replace(',', "")
+        };
+        normalized.parse::<Decimal>().ok()
+    } else {
+        cell_to_decimal(cell)
+    }
+}
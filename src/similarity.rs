@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+// The lowercase words of the text, without the numbers and the single letters
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1 && !word.chars().all(|ch| ch.is_ascii_digit()))
+        .map(str::to_lowercase)
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+// 1 - the edit distance relative to the longer text, ignoring the case and the spacing
+fn normalized_levenshtein(a: &str, b: &str) -> f64 {
+    let normalize = |text: &str| -> Vec<char> {
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+            .chars()
+            .collect()
+    };
+    let (a, b) = (normalize(a), normalize(b));
+    let longer = a.len().max(b.len());
+    if longer == 0 {
+        return 0.0;
+    }
+    1.0 - levenshtein(&a, &b) as f64 / longer as f64
+}
+
+// The common words relative to the shorter text, so 'Tesco' is similar to 'CARD TESCO BUDAPEST'
+fn token_overlap(a: &str, b: &str) -> f64 {
+    let a: HashSet<String> = tokenize(a).collect();
+    let b: HashSet<String> = tokenize(b).collect();
+    let shorter = a.len().min(b.len());
+    if shorter == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / shorter as f64
+}
+
+// How similar the two descriptions are, between 0 and 1
+pub fn similarity(a: &str, b: &str) -> f64 {
+    token_overlap(a, b).max(normalized_levenshtein(a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(similarity("Tesco", "CARD 1234 TESCO Budapest"), 1.0);
+        assert_eq!(similarity("Rent", "rent"), 1.0);
+        assert!(similarity("Tesco Budapest", "Tesko Budapest") > 0.9);
+        assert!(similarity("Spar", "Lidl") < 0.3);
+        assert_eq!(similarity("", "Tesco"), 0.0);
+    }
+}
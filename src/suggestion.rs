@@ -0,0 +1,200 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use anyhow::Result;
+use diesel::prelude::*;
+
+use crate::external_models::ExternalTransaction;
+use crate::models::Account;
+use crate::query::transactions::{TransactionQuery, load_counter_splits};
+use crate::similarity::tokenize;
+
+// Suggestions below this confidence are only offered interactively, never used automatically,
+// even with --accept-suggested
+pub const AUTO_CONFIDENCE: f64 = 0.9;
+
+pub struct Suggestion<'a> {
+    pub account: &'a Account,
+    pub confidence: f64,
+}
+
+impl fmt::Display for Suggestion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:.0}%)", self.account.name, self.confidence * 100.0)
+    }
+}
+
+#[derive(Default)]
+struct AccountStatistics {
+    transactions: usize,
+    token_count: usize,
+    tokens: HashMap<String, usize>,
+}
+
+// Naive Bayes classifier over the words of the descriptions and memos of the already booked
+// transactions, which predicts the counter account of a new transaction
+#[derive(Default)]
+pub struct AccountSuggester {
+    accounts: HashMap<String, Account>,
+    statistics: HashMap<String, AccountStatistics>,
+    vocabulary: HashSet<String>,
+    total_transactions: usize,
+}
+
+fn external_tokens(transaction: &ExternalTransaction) -> Vec<String> {
+    [
+        &transaction.description,
+        &transaction.other_account_name,
+        &transaction.category,
+    ]
+    .into_iter()
+    .flatten()
+    .flat_map(|text| tokenize(text))
+    .collect()
+}
+
+impl AccountSuggester {
+    // Learns from the transactions of the account, which have exactly one counter split
+    // in the same commodity
+    pub fn train(connection: &mut SqliteConnection, account: &Account) -> Result<Self> {
+        use crate::schema::accounts;
+
+        let query = TransactionQuery {
+            limit: i64::MAX,
+            txid_filter: None,
+            account_filter: Some(account.guid.clone()),
+            description_filter: None,
+            memo_filter: None,
+            before_filter: None,
+            after_filter: None,
+        };
+        let rows = query.execute(connection);
+        let counter_splits = load_counter_splits(connection, account, &rows)?;
+        let mut suggester = AccountSuggester::default();
+        for (split, tx) in &rows {
+            let Some([(counter, _)]) = counter_splits.get(&split.tx_guid).map(Vec::as_slice) else {
+                continue;
+            };
+            let texts = [
+                tx.description.as_deref(),
+                Some(&split.memo),
+                Some(&counter.memo),
+            ];
+            let tokens: Vec<String> = texts.into_iter().flatten().flat_map(tokenize).collect();
+            suggester.learn(&counter.account_guid, tokens);
+        }
+        let guids: Vec<&String> = suggester.statistics.keys().collect();
+        suggester.accounts = accounts::table
+            .filter(accounts::guid.eq_any(guids))
+            .filter(accounts::commodity_guid.eq(&account.commodity_guid))
+            .load::<Account>(connection)?
+            .into_iter()
+            .map(|account| (account.guid.clone(), account))
+            .collect();
+        Ok(suggester)
+    }
+
+    fn learn(&mut self, account_guid: &str, tokens: Vec<String>) {
+        let statistics = self.statistics.entry(account_guid.to_owned()).or_default();
+        statistics.transactions += 1;
+        statistics.token_count += tokens.len();
+        for token in tokens {
+            *statistics.tokens.entry(token.clone()).or_default() += 1;
+            self.vocabulary.insert(token);
+        }
+        self.total_transactions += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.total_transactions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_transactions == 0
+    }
+
+    // The most likely counter account, and its probability among the known accounts
+    pub fn suggest(&self, transaction: &ExternalTransaction) -> Option<Suggestion<'_>> {
+        let tokens = external_tokens(transaction);
+        // without a single known word, there is nothing to base the suggestion on
+        if !tokens.iter().any(|token| self.vocabulary.contains(token)) {
+            return None;
+        }
+        let vocabulary = self.vocabulary.len() as f64;
+        let scores: Vec<(&Account, f64)> = self
+            .accounts
+            .values()
+            .filter_map(|account| {
+                let statistics = self.statistics.get(&account.guid)?;
+                let prior = (statistics.transactions as f64 / self.total_transactions as f64).ln();
+                let likelihood: f64 = tokens
+                    .iter()
+                    .map(|token| {
+                        let count = statistics.tokens.get(token).copied().unwrap_or_default();
+                        ((count as f64 + 1.0) / (statistics.token_count as f64 + vocabulary)).ln()
+                    })
+                    .sum();
+                Some((account, prior + likelihood))
+            })
+            .collect();
+        let (best, best_score) = scores.iter().max_by(|a, b| a.1.total_cmp(&b.1)).copied()?;
+        // softmax over the log probabilities, relative to the best, to avoid underflow
+        let total: f64 = scores
+            .iter()
+            .map(|(_, score)| (score - best_score).exp())
+            .sum();
+        Some(Suggestion {
+            account: best,
+            confidence: 1.0 / total,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::account;
+
+    fn external(description: &str) -> ExternalTransaction {
+        ExternalTransaction {
+            date: None,
+            booking_date: None,
+            amount: None,
+            category: None,
+            description: Some(description.to_owned()),
+            other_account: None,
+            other_account_name: None,
+            textual_date: None,
+            transaction_fee: None,
+            reference: None,
+        }
+    }
+
+    #[test]
+    fn test_suggest_by_words() {
+        let mut suggester = AccountSuggester::default();
+        for (guid, text) in [
+            ("food", "Tesco groceries"),
+            ("food", "TESCO Budapest 1234"),
+            ("food", "Lidl"),
+            ("rent", "Rent March"),
+            ("rent", "Rent April"),
+        ] {
+            suggester.learn(guid, tokenize(text).collect());
+        }
+        for guid in ["food", "rent"] {
+            suggester
+                .accounts
+                .insert(guid.to_owned(), account(guid, guid, "huf"));
+        }
+        let suggestion = suggester.suggest(&external("CARD tesco 42")).unwrap();
+        assert_eq!(suggestion.account.guid, "food");
+        assert!(suggestion.confidence > 0.5 && suggestion.confidence <= 1.0);
+        assert_eq!(
+            suggester.suggest(&external("rent")).unwrap().account.guid,
+            "rent"
+        );
+        assert!(suggester.suggest(&external("12 34")).is_none());
+        assert!(suggester.suggest(&external("Spar")).is_none());
+    }
+}
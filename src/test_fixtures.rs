@@ -0,0 +1,98 @@
+// The book, the accounts and the rows shared by the tests
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::sql_query;
+use rust_decimal::Decimal;
+
+use crate::external_models::ExternalTransaction;
+use crate::models::{Account, Split, Transaction};
+
+// The tables, as GnuCash creates them, with the forint as the only commodity
+const BOOK_TABLES: [&str; 4] = [
+    "CREATE TABLE commodities(guid text(32) PRIMARY KEY NOT NULL, \
+     namespace text(2048) NOT NULL, mnemonic text(2048) NOT NULL, fullname text(2048), \
+     cusip text(2048), fraction integer NOT NULL, quote_flag integer NOT NULL, \
+     quote_source text(2048), quote_tz text(2048))",
+    "INSERT INTO commodities VALUES ('huf', 'CURRENCY', 'HUF', NULL, NULL, 100, 0, NULL, NULL)",
+    "CREATE TABLE transactions(guid text(32) PRIMARY KEY NOT NULL, \
+     currency_guid text(32) NOT NULL, num text(2048) NOT NULL, post_date text(19), \
+     enter_date text(19), description text(2048))",
+    "CREATE TABLE splits(guid text(32) PRIMARY KEY NOT NULL, tx_guid text(32) NOT NULL, \
+     account_guid text(32) NOT NULL, memo text(2048) NOT NULL, action text(2048) NOT NULL, \
+     reconcile_state text(1) NOT NULL, reconcile_date text(19), value_num bigint NOT NULL, \
+     value_denom bigint NOT NULL, quantity_num bigint NOT NULL, \
+     quantity_denom bigint NOT NULL, lot_guid text(32))",
+];
+
+// An in-memory book, filled by the given statements
+pub fn book(statements: &[&str]) -> SqliteConnection {
+    let mut connection = SqliteConnection::establish(":memory:").unwrap();
+    for statement in BOOK_TABLES.iter().chain(statements) {
+        sql_query(*statement).execute(&mut connection).unwrap();
+    }
+    connection
+}
+
+pub fn account(guid: &str, name: &str, commodity: &str) -> Account {
+    Account {
+        guid: guid.to_owned(),
+        name: name.to_owned(),
+        account_type: "EXPENSE".to_owned(),
+        commodity_guid: Some(commodity.to_owned()),
+        commodity_scu: 100,
+        non_std_scu: 0,
+        parent_guid: None,
+        code: None,
+        description: None,
+        hidden: None,
+        placeholder: None,
+    }
+}
+
+// A statement row of January 2024
+pub fn external(day: u32, amount: i64, description: &str) -> ExternalTransaction {
+    ExternalTransaction {
+        date: NaiveDate::from_ymd_opt(2024, 1, day),
+        booking_date: None,
+        amount: Some(Decimal::from(amount)),
+        category: None,
+        description: Some(description.to_owned()),
+        other_account: None,
+        other_account_name: None,
+        textual_date: None,
+        transaction_fee: None,
+        reference: None,
+    }
+}
+
+// A transaction booked on the date, like 2024-01-05, with its split in the bank account
+pub fn booked_on(guid: &str, date: &str, amount: i64, description: &str) -> (Split, Transaction) {
+    let split = Split {
+        guid: format!("s{}", guid),
+        tx_guid: guid.to_owned(),
+        account_guid: "bank".to_owned(),
+        memo: String::new(),
+        action: String::new(),
+        reconcile_state: "n".to_owned(),
+        reconcile_date: None,
+        value_num: amount,
+        value_denom: 1,
+        quantity_num: amount,
+        quantity_denom: 1,
+        lot_guid: None,
+    };
+    let transaction = Transaction {
+        guid: guid.to_owned(),
+        currency_guid: "huf".to_owned(),
+        num: String::new(),
+        post_date: Some(format!("{} 10:00:00", date)),
+        enter_date: None,
+        description: Some(description.to_owned()),
+    };
+    (split, transaction)
+}
+
+// A transaction booked on the day of January 2024
+pub fn booked(guid: &str, day: u32, amount: i64, description: &str) -> (Split, Transaction) {
+    booked_on(guid, &format!("2024-01-{:02}", day), amount, description)
+}
@@ -1,5 +1,7 @@
 use chrono::{NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
 use dotenv::dotenv;
 use regex::Regex;
 use rust_decimal::Decimal;
@@ -14,6 +16,21 @@ pub fn establish_connection() -> SqliteConnection {
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
 
+#[derive(QueryableByName)]
+struct TableCount {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+// Whether the book has the given table of financ, checked without creating it
+pub fn table_exists(connection: &mut SqliteConnection, name: &str) -> QueryResult<bool> {
+    let tables =
+        sql_query("SELECT count(*) AS count FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind::<Text, _>(name)
+            .get_result::<TableCount>(connection)?;
+    Ok(tables.count > 0)
+}
+
 pub fn to_date(date_string: Option<String>) -> Option<NaiveDate> {
     date_string.and_then(|x| NaiveDate::parse_from_str(x.as_ref(), "%Y-%m-%d").ok())
 }